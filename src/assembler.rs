@@ -0,0 +1,1130 @@
+//! Assembler for Octo source, the de facto CHIP-8 assembly language.
+//!
+//! Supported: labels (`: name`), `:const`, `:alias`, `:macro`, `:calc`, `:byte`, `:pointer`,
+//! `:org`, `:next`, `:unpack` and `:call`, every CHIP-8, SUPER-CHIP and XO-CHIP statement,
+//! `if ... then` and `if ... begin ... else ... end`, `loop ... while ... again`, and numbers on
+//! their own for sprite and other data. `:breakpoint` and `:monitor` are accepted and ignored.
+//!
+//! Like Octo, tokens are separated by whitespace, `#` starts a comment, and `:calc` expressions
+//! are evaluated right to left without operator precedence. The program runs from 0x200, so
+//! unless the source starts with `: main` a `jump main` is put there first.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+const START_ADDR: usize = 0x200;
+const MAX_ADDR: usize = 0xffff;
+// stops a macro that expands to itself
+const MAX_EXPANSIONS: usize = 10_000;
+// Octo's conditionals that compare with < and > work on this register
+const COMPARE_REGISTER: u8 = 0xf;
+
+/// A problem with the source, pointing at the token that caused it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssembleError {
+    /// 1 based
+    pub line: usize,
+    /// 1 based
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+impl std::error::Error for AssembleError {}
+
+/// An assembled program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assembly {
+    /// the program from 0x200 on, ready for `Emulator::load`
+    pub rom: Vec<u8>,
+    /// every label and its address
+    pub labels: BTreeMap<String, u16>,
+}
+
+impl Assembly {
+    /// Lists the labels by address, one `ADDR name` per line.
+    pub fn symbol_file(&self) -> String {
+        let mut symbols: Vec<(&u16, &String)> = self
+            .labels
+            .iter()
+            .map(|(name, address)| (address, name))
+            .collect();
+        symbols.sort();
+        symbols
+            .into_iter()
+            .map(|(address, name)| format!("{:04X} {}\n", address, name))
+            .collect()
+    }
+}
+
+pub fn assemble(source: &str) -> Result<Assembly, AssembleError> {
+    let mut assembler = Assembler::new(tokenize(source));
+    assembler.run()?;
+    Ok(Assembly {
+        rom: assembler.rom,
+        labels: assembler.labels,
+    })
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    line: usize,
+    column: usize,
+}
+
+impl Token {
+    fn error(&self, message: impl Into<String>) -> AssembleError {
+        AssembleError {
+            line: self.line,
+            column: self.column,
+            message: message.into(),
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    for (line_index, line) in source.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("");
+        let mut start = None;
+        // columns count characters, end with a space so the last token gets pushed
+        for (column, c) in line.chars().chain(std::iter::once(' ')).enumerate() {
+            match (c.is_whitespace(), start) {
+                (false, None) => start = Some(column),
+                (true, Some(first)) => {
+                    tokens.push(Token {
+                        text: line.chars().skip(first).take(column - first).collect(),
+                        line: line_index + 1,
+                        column: first + 1,
+                    });
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+    }
+    tokens
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()?
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+fn parse_register(text: &str) -> Option<u8> {
+    let digit = text.strip_prefix('v').or(text.strip_prefix('V'))?;
+    if digit.len() != 1 {
+        return None;
+    }
+    u8::from_str_radix(digit, 16).ok()
+}
+
+/// How a forward reference is written once the label is known.
+#[derive(Debug, Clone, Copy)]
+enum FixupKind {
+    /// the low 12 bits of an instruction
+    Nnn,
+    /// a full 16 bit word
+    Word,
+}
+
+struct Fixup {
+    address: usize,
+    kind: FixupKind,
+    token: Token,
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+/// A comparison in `if`, `while` and so on.
+enum Condition {
+    Equal(u8, Operand),
+    NotEqual(u8, Operand),
+    Less(u8, Operand),
+    Greater(u8, Operand),
+    LessOrEqual(u8, Operand),
+    GreaterOrEqual(u8, Operand),
+    Key(u8),
+    NotKey(u8),
+}
+
+#[derive(Clone, Copy)]
+enum Operand {
+    Register(u8),
+    Byte(u8),
+}
+
+impl Condition {
+    fn negate(self) -> Condition {
+        match self {
+            Condition::Equal(x, operand) => Condition::NotEqual(x, operand),
+            Condition::NotEqual(x, operand) => Condition::Equal(x, operand),
+            Condition::Less(x, operand) => Condition::GreaterOrEqual(x, operand),
+            Condition::GreaterOrEqual(x, operand) => Condition::Less(x, operand),
+            Condition::Greater(x, operand) => Condition::LessOrEqual(x, operand),
+            Condition::LessOrEqual(x, operand) => Condition::Greater(x, operand),
+            Condition::Key(x) => Condition::NotKey(x),
+            Condition::NotKey(x) => Condition::Key(x),
+        }
+    }
+}
+
+/// An open `if ... begin`, waiting for its `else` or `end`.
+struct Branch {
+    /// the jump that needs to point past the block
+    jump: usize,
+    token: Token,
+}
+
+/// An open `loop`, waiting for its `again`.
+struct Loop {
+    start: usize,
+    /// jumps out of the loop from `while`
+    exits: Vec<usize>,
+    token: Token,
+}
+
+struct Assembler {
+    tokens: Vec<Token>,
+    position: usize,
+    expansions: usize,
+    rom: Vec<u8>,
+    here: usize,
+    labels: BTreeMap<String, u16>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    branches: Vec<Branch>,
+    loops: Vec<Loop>,
+}
+
+impl Assembler {
+    fn new(tokens: Vec<Token>) -> Self {
+        Self {
+            tokens,
+            position: 0,
+            expansions: 0,
+            rom: Vec::new(),
+            here: START_ADDR,
+            labels: BTreeMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            branches: Vec::new(),
+            loops: Vec::new(),
+        }
+    }
+
+    fn run(&mut self) -> Result<(), AssembleError> {
+        let starts_with_main =
+            self.tokens.len() >= 2 && self.tokens[0].text == ":" && self.tokens[1].text == "main";
+        if !starts_with_main && !self.tokens.is_empty() {
+            let token = Token {
+                text: "main".to_string(),
+                line: 1,
+                column: 1,
+            };
+            self.emit_fixup(0x1000, token)?;
+        }
+        while self.position < self.tokens.len() {
+            self.statement()?;
+        }
+        if let Some(branch) = self.branches.last() {
+            return Err(branch.token.error("missing end"));
+        }
+        if let Some(open) = self.loops.last() {
+            return Err(open.token.error("missing again"));
+        }
+        for fixup in std::mem::take(&mut self.fixups) {
+            let address = match self.labels.get(&fixup.token.text) {
+                Some(&address) => address,
+                None if fixup.token.text == "main" => {
+                    return Err(fixup.token.error("the program has no main label"))
+                }
+                None => {
+                    return Err(fixup
+                        .token
+                        .error(format!("undefined name {}", fixup.token.text)))
+                }
+            };
+            let index = fixup.address - START_ADDR;
+            match fixup.kind {
+                FixupKind::Nnn => {
+                    if address > 0xfff {
+                        return Err(fixup.token.error("address doesn't fit in 12 bits"));
+                    }
+                    self.rom[index] |= (address >> 8) as u8;
+                    self.rom[index + 1] = address as u8;
+                }
+                FixupKind::Word => {
+                    self.rom[index..index + 2].copy_from_slice(&address.to_be_bytes());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<Token, AssembleError> {
+        match self.tokens.get(self.position) {
+            Some(token) => {
+                self.position += 1;
+                Ok(token.clone())
+            }
+            None => Err(self
+                .tokens
+                .last()
+                .map(|token| token.error("unexpected end of source"))
+                .unwrap_or(AssembleError {
+                    line: 1,
+                    column: 1,
+                    message: "unexpected end of source".to_string(),
+                })),
+        }
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens
+            .get(self.position)
+            .map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, text: &str) -> Result<Token, AssembleError> {
+        let token = self.next()?;
+        if token.text != text {
+            return Err(token.error(format!("expected {} but found {}", text, token.text)));
+        }
+        Ok(token)
+    }
+
+    fn emit(&mut self, byte: u8, token: &Token) -> Result<(), AssembleError> {
+        if self.here > MAX_ADDR {
+            return Err(token.error("program doesn't fit in memory"));
+        }
+        let index = self.here - START_ADDR;
+        if index >= self.rom.len() {
+            self.rom.resize(index + 1, 0);
+        }
+        self.rom[index] = byte;
+        self.here += 1;
+        Ok(())
+    }
+
+    fn emit_op(&mut self, opcode: u16, token: &Token) -> Result<(), AssembleError> {
+        self.emit((opcode >> 8) as u8, token)?;
+        self.emit(opcode as u8, token)
+    }
+
+    /// Emits an instruction whose low 12 bits are the address `token` names, which may not be
+    /// defined yet.
+    fn emit_fixup(&mut self, opcode: u16, token: Token) -> Result<(), AssembleError> {
+        if let Some(value) = self.known_value(&token) {
+            let address = self.check_range(value, 0xfff, &token)? as u16;
+            return self.emit_op(opcode | address, &token);
+        }
+        self.check_name(&token)?;
+        let address = self.here;
+        self.emit_op(opcode, &token)?;
+        self.fixups.push(Fixup {
+            address,
+            kind: FixupKind::Nnn,
+            token,
+        });
+        Ok(())
+    }
+
+    /// Writes a jump to `target` over the placeholder at `address`.
+    fn patch_jump(
+        &mut self,
+        address: usize,
+        target: usize,
+        token: &Token,
+    ) -> Result<(), AssembleError> {
+        if target > 0xfff {
+            return Err(token.error("jump target doesn't fit in 12 bits"));
+        }
+        let opcode = 0x1000 | target as u16;
+        self.rom[address - START_ADDR..][..2].copy_from_slice(&opcode.to_be_bytes());
+        Ok(())
+    }
+
+    fn check_range(&self, value: i64, max: i64, token: &Token) -> Result<i64, AssembleError> {
+        if !(0..=max).contains(&value) {
+            return Err(token.error(format!("{} is out of range 0-{:#X}", value, max)));
+        }
+        Ok(value)
+    }
+
+    fn check_name(&self, token: &Token) -> Result<(), AssembleError> {
+        let valid = token
+            .text
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+            && !token
+                .text
+                .starts_with(|c: char| c.is_ascii_digit() || c == '-');
+        if !valid {
+            return Err(token.error(format!("invalid name {}", token.text)));
+        }
+        Ok(())
+    }
+
+    /// A number, constant or already defined label.
+    fn known_value(&self, token: &Token) -> Option<i64> {
+        parse_number(&token.text)
+            .or_else(|| self.constants.get(&token.text).map(|&value| value as i64))
+            .or_else(|| self.labels.get(&token.text).map(|&address| address as i64))
+    }
+
+    fn value(&mut self, max: i64) -> Result<i64, AssembleError> {
+        let token = self.next()?;
+        let value = self
+            .known_value(&token)
+            .ok_or_else(|| token.error(format!("undefined name {}", token.text)))?;
+        self.check_range(value, max, &token)
+    }
+
+    /// An 8 bit value, negative numbers wrap around like Octo.
+    fn byte(&mut self) -> Result<u8, AssembleError> {
+        let token = self.next()?;
+        let value = self
+            .known_value(&token)
+            .ok_or_else(|| token.error(format!("undefined name {}", token.text)))?;
+        if !(-128..=255).contains(&value) {
+            return Err(token.error(format!("{} doesn't fit in a byte", value)));
+        }
+        Ok(value as u8)
+    }
+
+    fn register_of(&self, token: &Token) -> Option<u8> {
+        parse_register(&token.text).or_else(|| self.aliases.get(&token.text).copied())
+    }
+
+    fn register(&mut self) -> Result<u8, AssembleError> {
+        let token = self.next()?;
+        self.register_of(&token)
+            .ok_or_else(|| token.error(format!("expected a register but found {}", token.text)))
+    }
+
+    fn operand(&mut self) -> Result<Operand, AssembleError> {
+        let token = self.tokens.get(self.position).cloned();
+        match token.as_ref().and_then(|token| self.register_of(token)) {
+            Some(register) => {
+                self.position += 1;
+                Ok(Operand::Register(register))
+            }
+            None => Ok(Operand::Byte(self.byte()?)),
+        }
+    }
+
+    fn define_label(&mut self, token: &Token, address: usize) -> Result<(), AssembleError> {
+        self.check_name(token)?;
+        if self.labels.contains_key(&token.text) {
+            return Err(token.error(format!("{} is already defined", token.text)));
+        }
+        self.labels.insert(token.text.clone(), address as u16);
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<(), AssembleError> {
+        let token = self.next()?;
+        let t = &token;
+        match token.text.as_str() {
+            ":" => {
+                let name = self.next()?;
+                self.define_label(&name, self.here)?;
+            }
+            ":next" => {
+                // labels the second byte of the next instruction, for self modifying code
+                let name = self.next()?;
+                self.define_label(&name, self.here + 1)?;
+            }
+            ":const" => {
+                let name = self.next()?;
+                self.check_name(&name)?;
+                let value = self.value(i64::MAX)?;
+                self.constants.insert(name.text, value as f64);
+            }
+            ":alias" => {
+                let name = self.next()?;
+                self.check_name(&name)?;
+                let register = self.register()?;
+                self.aliases.insert(name.text, register);
+            }
+            ":calc" => {
+                let name = self.next()?;
+                self.check_name(&name)?;
+                let value = self.calc_block()?;
+                self.constants.insert(name.text, value);
+            }
+            ":byte" => {
+                let value = if self.peek() == Some("{") {
+                    self.calc_block()? as i64 as u8
+                } else {
+                    self.byte()?
+                };
+                self.emit(value, t)?;
+            }
+            ":pointer" => {
+                let target = self.next()?;
+                match self.known_value(&target) {
+                    Some(value) => {
+                        let value = self.check_range(value, 0xffff, &target)? as u16;
+                        self.emit_op(value, t)?;
+                    }
+                    None => {
+                        self.check_name(&target)?;
+                        let address = self.here;
+                        self.emit_op(0, t)?;
+                        self.fixups.push(Fixup {
+                            address,
+                            kind: FixupKind::Word,
+                            token: target,
+                        });
+                    }
+                }
+            }
+            ":org" => {
+                let address = self.value(MAX_ADDR as i64)? as usize;
+                if address < START_ADDR {
+                    return Err(t.error("programs start at 0x200"));
+                }
+                self.here = address;
+            }
+            ":unpack" => {
+                // v0 := high nibble and the top of the address, v1 := the rest of the address
+                let nibble = self.value(0xf)? as u16;
+                let address = self.value(0xfff)? as u16;
+                self.emit_op(0x6000 | (nibble << 4) | (address >> 8), t)?;
+                self.emit_op(0x6100 | (address & 0xff), t)?;
+            }
+            ":call" => {
+                let target = self.next()?;
+                self.emit_fixup(0x2000, target)?;
+            }
+            ":macro" => self.define_macro()?,
+            ":breakpoint" => {
+                self.next()?;
+            }
+            ":monitor" => {
+                self.next()?;
+                self.next()?;
+            }
+            "clear" => self.emit_op(0x00e0, t)?,
+            "return" | ";" => self.emit_op(0x00ee, t)?,
+            "exit" => self.emit_op(0x00fd, t)?,
+            "lores" => self.emit_op(0x00fe, t)?,
+            "hires" => self.emit_op(0x00ff, t)?,
+            "scroll-left" => self.emit_op(0x00fc, t)?,
+            "scroll-right" => self.emit_op(0x00fb, t)?,
+            "scroll-down" => {
+                let rows = self.value(0xf)? as u16;
+                self.emit_op(0x00c0 | rows, t)?;
+            }
+            "scroll-up" => {
+                let rows = self.value(0xf)? as u16;
+                self.emit_op(0x00d0 | rows, t)?;
+            }
+            "audio" => self.emit_op(0xf002, t)?,
+            "plane" => {
+                let planes = self.value(0xf)? as u16;
+                self.emit_op(0xf001 | (planes << 8), t)?;
+            }
+            "jump" => {
+                let target = self.next()?;
+                self.emit_fixup(0x1000, target)?;
+            }
+            "jump0" => {
+                let target = self.next()?;
+                self.emit_fixup(0xb000, target)?;
+            }
+            "native" => {
+                let target = self.next()?;
+                self.emit_fixup(0x0000, target)?;
+            }
+            "sprite" => {
+                let x = self.register()? as u16;
+                let y = self.register()? as u16;
+                let rows = self.value(0xf)? as u16;
+                self.emit_op(0xd000 | (x << 8) | (y << 4) | rows, t)?;
+            }
+            "bcd" => self.register_op(0xf033, t)?,
+            "saveflags" => self.register_op(0xf075, t)?,
+            "loadflags" => self.register_op(0xf085, t)?,
+            "save" | "load" => {
+                let x = self.register()? as u16;
+                if self.peek() == Some("-") {
+                    self.next()?;
+                    let y = self.register()? as u16;
+                    let low = if token.text == "save" { 2 } else { 3 };
+                    self.emit_op(0x5000 | (x << 8) | (y << 4) | low, t)?;
+                } else {
+                    let opcode = if token.text == "save" { 0xf055 } else { 0xf065 };
+                    self.emit_op(opcode | (x << 8), t)?;
+                }
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let opcode = match token.text.as_str() {
+                    "delay" => 0xf015,
+                    "buzzer" => 0xf018,
+                    _ => 0xf03a,
+                };
+                self.register_op(opcode, t)?;
+            }
+            "i" => self.i_statement()?,
+            "if" => self.if_statement(t)?,
+            "else" => {
+                let branch = self
+                    .branches
+                    .pop()
+                    .ok_or_else(|| t.error("else without if ... begin"))?;
+                // the true block jumps over the else block
+                let jump = self.here;
+                self.emit_op(0x1000, t)?;
+                self.patch_jump(branch.jump, self.here, t)?;
+                self.branches.push(Branch {
+                    jump,
+                    token: token.clone(),
+                });
+            }
+            "end" => {
+                let branch = self
+                    .branches
+                    .pop()
+                    .ok_or_else(|| t.error("end without if ... begin"))?;
+                self.patch_jump(branch.jump, self.here, t)?;
+            }
+            "loop" => self.loops.push(Loop {
+                start: self.here,
+                exits: Vec::new(),
+                token: token.clone(),
+            }),
+            "while" => {
+                if self.loops.is_empty() {
+                    return Err(t.error("while outside of a loop"));
+                }
+                let condition = self.condition()?;
+                self.emit_skip_unless(condition.negate(), t)?;
+                let jump = self.here;
+                self.emit_op(0x1000, t)?;
+                self.loops
+                    .last_mut()
+                    .expect("checked above")
+                    .exits
+                    .push(jump);
+            }
+            "again" => {
+                let open = self
+                    .loops
+                    .pop()
+                    .ok_or_else(|| t.error("again without loop"))?;
+                self.emit_op(0x1000, t)?;
+                self.patch_jump(self.here - 2, open.start, t)?;
+                for exit in open.exits {
+                    self.patch_jump(exit, self.here, t)?;
+                }
+            }
+            _ => {
+                if let Some(x) = self.register_of(&token) {
+                    return self.register_statement(x as u16, t);
+                }
+                if self.macros.contains_key(&token.text) {
+                    return self.expand_macro(t);
+                }
+                if let Some(value) = parse_number(&token.text)
+                    .or_else(|| self.constants.get(&token.text).map(|&value| value as i64))
+                {
+                    // numbers on their own are data
+                    if !(-128..=255).contains(&value) {
+                        return Err(t.error(format!("{} doesn't fit in a byte", value)));
+                    }
+                    return self.emit(value as u8, t);
+                }
+                if token.text.starts_with(':') {
+                    return Err(t.error(format!("unknown directive {}", token.text)));
+                }
+                // anything else names a subroutine, which may be defined later
+                self.emit_fixup(0x2000, token.clone())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Emits an `FX..` style instruction taking the register that comes next.
+    fn register_op(&mut self, opcode: u16, token: &Token) -> Result<(), AssembleError> {
+        let x = self.register()? as u16;
+        self.emit_op(opcode | (x << 8), token)
+    }
+
+    fn i_statement(&mut self) -> Result<(), AssembleError> {
+        let op = self.next()?;
+        match op.text.as_str() {
+            "+=" => self.register_op(0xf01e, &op),
+            ":=" => match self.peek() {
+                Some("hex") => {
+                    self.next()?;
+                    self.register_op(0xf029, &op)
+                }
+                Some("bighex") => {
+                    self.next()?;
+                    self.register_op(0xf030, &op)
+                }
+                Some("long") => {
+                    self.next()?;
+                    self.emit_op(0xf000, &op)?;
+                    let target = self.next()?;
+                    match self.known_value(&target) {
+                        Some(value) => {
+                            let value = self.check_range(value, 0xffff, &target)? as u16;
+                            self.emit_op(value, &op)
+                        }
+                        None => {
+                            self.check_name(&target)?;
+                            let address = self.here;
+                            self.emit_op(0, &op)?;
+                            self.fixups.push(Fixup {
+                                address,
+                                kind: FixupKind::Word,
+                                token: target,
+                            });
+                            Ok(())
+                        }
+                    }
+                }
+                _ => {
+                    let target = self.next()?;
+                    self.emit_fixup(0xa000, target)
+                }
+            },
+            _ => Err(op.error(format!("expected := or += after i but found {}", op.text))),
+        }
+    }
+
+    fn register_statement(&mut self, x: u16, token: &Token) -> Result<(), AssembleError> {
+        let op = self.next()?;
+        let t = &op;
+        match op.text.as_str() {
+            ":=" => match self.peek() {
+                Some("random") => {
+                    self.next()?;
+                    let mask = self.byte()? as u16;
+                    self.emit_op(0xc000 | (x << 8) | mask, t)
+                }
+                Some("key") => {
+                    self.next()?;
+                    self.emit_op(0xf00a | (x << 8), t)
+                }
+                Some("delay") => {
+                    self.next()?;
+                    self.emit_op(0xf007 | (x << 8), t)
+                }
+                _ => match self.operand()? {
+                    Operand::Register(y) => self.emit_op(0x8000 | (x << 8) | ((y as u16) << 4), t),
+                    Operand::Byte(nn) => self.emit_op(0x6000 | (x << 8) | nn as u16, t),
+                },
+            },
+            "+=" => match self.operand()? {
+                Operand::Register(y) => self.emit_op(0x8004 | (x << 8) | ((y as u16) << 4), t),
+                Operand::Byte(nn) => self.emit_op(0x7000 | (x << 8) | nn as u16, t),
+            },
+            "-=" => match self.operand()? {
+                Operand::Register(y) => self.emit_op(0x8005 | (x << 8) | ((y as u16) << 4), t),
+                // there's no subtract immediate, so add the negative
+                Operand::Byte(nn) => self.emit_op(0x7000 | (x << 8) | nn.wrapping_neg() as u16, t),
+            },
+            "|=" | "&=" | "^=" | "=-" | ">>=" | "<<=" => {
+                let y = self.register()? as u16;
+                let low = match op.text.as_str() {
+                    "|=" => 1,
+                    "&=" => 2,
+                    "^=" => 3,
+                    "=-" => 7,
+                    ">>=" => 6,
+                    _ => 0xe,
+                };
+                self.emit_op(0x8000 | (x << 8) | (y << 4) | low, t)
+            }
+            _ => Err(token.error(format!(
+                "expected an assignment after {} but found {}",
+                token.text, op.text
+            ))),
+        }
+    }
+
+    fn condition(&mut self) -> Result<Condition, AssembleError> {
+        let x = self.register()?;
+        let op = self.next()?;
+        let condition = match op.text.as_str() {
+            "key" => Condition::Key(x),
+            "-key" => Condition::NotKey(x),
+            "==" => Condition::Equal(x, self.operand()?),
+            "!=" => Condition::NotEqual(x, self.operand()?),
+            "<" => Condition::Less(x, self.operand()?),
+            ">" => Condition::Greater(x, self.operand()?),
+            "<=" => Condition::LessOrEqual(x, self.operand()?),
+            ">=" => Condition::GreaterOrEqual(x, self.operand()?),
+            _ => return Err(op.error(format!("expected a comparison but found {}", op.text))),
+        };
+        Ok(condition)
+    }
+
+    /// Emits instructions that skip the next one unless `condition` is true.
+    fn emit_skip_unless(
+        &mut self,
+        condition: Condition,
+        token: &Token,
+    ) -> Result<(), AssembleError> {
+        let vf = COMPARE_REGISTER as u16;
+        let compare = |x: u8| (x as u16) << 8;
+        match condition {
+            Condition::Equal(x, Operand::Byte(nn)) => {
+                self.emit_op(0x4000 | compare(x) | nn as u16, token)
+            }
+            Condition::NotEqual(x, Operand::Byte(nn)) => {
+                self.emit_op(0x3000 | compare(x) | nn as u16, token)
+            }
+            Condition::Equal(x, Operand::Register(y)) => {
+                self.emit_op(0x9000 | compare(x) | ((y as u16) << 4), token)
+            }
+            Condition::NotEqual(x, Operand::Register(y)) => {
+                self.emit_op(0x5000 | compare(x) | ((y as u16) << 4), token)
+            }
+            Condition::Key(x) => self.emit_op(0xe0a1 | compare(x), token),
+            Condition::NotKey(x) => self.emit_op(0xe09e | compare(x), token),
+            Condition::Less(x, operand)
+            | Condition::GreaterOrEqual(x, operand)
+            | Condition::Greater(x, operand)
+            | Condition::LessOrEqual(x, operand) => {
+                // the comparison is done in VF, so it can't be one of the sides
+                if x == COMPARE_REGISTER || matches!(operand, Operand::Register(COMPARE_REGISTER)) {
+                    return Err(token.error("vf can't be compared with <, >, <= or >="));
+                }
+                // leave VF = 1 when x >= operand (or operand >= x), which is the carry flag
+                // of a subtraction done in VF
+                let x_first = matches!(
+                    condition,
+                    Condition::Less(..) | Condition::GreaterOrEqual(..)
+                );
+                match (operand, x_first) {
+                    // VF := VX, VF -= VY
+                    (Operand::Register(y), true) => {
+                        self.emit_op(0x8000 | (vf << 8) | ((x as u16) << 4), token)?;
+                        self.emit_op(0x8005 | (vf << 8) | ((y as u16) << 4), token)?;
+                    }
+                    // VF := VY, VF -= VX
+                    (Operand::Register(y), false) => {
+                        self.emit_op(0x8000 | (vf << 8) | ((y as u16) << 4), token)?;
+                        self.emit_op(0x8005 | (vf << 8) | ((x as u16) << 4), token)?;
+                    }
+                    // VF := NN, VF =- VX
+                    (Operand::Byte(nn), true) => {
+                        self.emit_op(0x6000 | (vf << 8) | nn as u16, token)?;
+                        self.emit_op(0x8007 | (vf << 8) | ((x as u16) << 4), token)?;
+                    }
+                    // VF := NN, VF -= VX
+                    (Operand::Byte(nn), false) => {
+                        self.emit_op(0x6000 | (vf << 8) | nn as u16, token)?;
+                        self.emit_op(0x8005 | (vf << 8) | ((x as u16) << 4), token)?;
+                    }
+                }
+                let flag_when_true = matches!(
+                    condition,
+                    Condition::GreaterOrEqual(..) | Condition::LessOrEqual(..)
+                );
+                // skip when VF isn't what it is when the condition holds
+                self.emit_op(0x4000 | (vf << 8) | flag_when_true as u16, token)
+            }
+        }
+    }
+
+    fn if_statement(&mut self, token: &Token) -> Result<(), AssembleError> {
+        let condition = self.condition()?;
+        let keyword = self.next()?;
+        match keyword.text.as_str() {
+            "then" => self.emit_skip_unless(condition, token),
+            "begin" => {
+                // skip the jump over the block when the condition holds
+                self.emit_skip_unless(condition.negate(), token)?;
+                let jump = self.here;
+                self.emit_op(0x1000, token)?;
+                self.branches.push(Branch {
+                    jump,
+                    token: token.clone(),
+                });
+                Ok(())
+            }
+            _ => Err(keyword.error(format!("expected then or begin but found {}", keyword.text))),
+        }
+    }
+
+    fn define_macro(&mut self) -> Result<(), AssembleError> {
+        let name = self.next()?;
+        self.check_name(&name)?;
+        let mut params = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token.text == "{" {
+                break;
+            }
+            params.push(token.text);
+        }
+        let body = self.block()?;
+        self.macros.insert(name.text, Macro { params, body });
+        Ok(())
+    }
+
+    /// The tokens up to the `}` matching a `{` that has already been read.
+    fn block(&mut self) -> Result<Vec<Token>, AssembleError> {
+        let mut depth = 1;
+        let mut body = Vec::new();
+        loop {
+            let token = self.next()?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(body);
+                    }
+                }
+                _ => {}
+            }
+            body.push(token);
+        }
+    }
+
+    /// Replaces a macro call with its body, arguments substituted, for the next statements.
+    fn expand_macro(&mut self, token: &Token) -> Result<(), AssembleError> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return Err(token.error("too many macro expansions, is a macro calling itself?"));
+        }
+        let params = self.macros[&token.text].params.clone();
+        let mut arguments = HashMap::new();
+        for param in params {
+            arguments.insert(param, self.next()?.text);
+        }
+        let body: Vec<Token> = self.macros[&token.text]
+            .body
+            .iter()
+            .map(|body_token| match arguments.get(&body_token.text) {
+                Some(argument) => Token {
+                    text: argument.clone(),
+                    ..body_token.clone()
+                },
+                None => body_token.clone(),
+            })
+            .collect();
+        self.tokens.splice(self.position..self.position, body);
+        Ok(())
+    }
+
+    /// Evaluates a `{ ... }` calc expression.
+    fn calc_block(&mut self) -> Result<f64, AssembleError> {
+        let open = self.expect("{")?;
+        let tokens = self.block()?;
+        let mut calc = Calc {
+            assembler: self,
+            tokens: &tokens,
+            position: 0,
+            open: &open,
+        };
+        let value = calc.expression()?;
+        if let Some(extra) = tokens.get(calc.position) {
+            return Err(extra.error(format!("unexpected {} in expression", extra.text)));
+        }
+        Ok(value)
+    }
+}
+
+/// Evaluates `:calc` expressions, right to left with no precedence like Octo.
+struct Calc<'a> {
+    assembler: &'a Assembler,
+    tokens: &'a [Token],
+    position: usize,
+    open: &'a Token,
+}
+
+impl Calc<'_> {
+    fn next(&mut self) -> Result<&Token, AssembleError> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .ok_or_else(|| self.open.error("incomplete expression"))?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn expression(&mut self) -> Result<f64, AssembleError> {
+        let left = self.term()?;
+        let Some(op) = self.tokens.get(self.position) else {
+            return Ok(left);
+        };
+        let op = op.clone();
+        let apply: fn(f64, f64) -> f64 = match op.text.as_str() {
+            "+" => |a, b| a + b,
+            "-" => |a, b| a - b,
+            "*" => |a, b| a * b,
+            "/" => |a, b| a / b,
+            "%" => |a, b| a % b,
+            "&" => |a, b| (a as i64 & b as i64) as f64,
+            "|" => |a, b| (a as i64 | b as i64) as f64,
+            "^" => |a, b| (a as i64 ^ b as i64) as f64,
+            "<<" => |a, b| ((a as i64) << (b as i64)) as f64,
+            ">>" => |a, b| ((a as i64) >> (b as i64)) as f64,
+            "pow" => f64::powf,
+            "min" => f64::min,
+            "max" => f64::max,
+            "<" => |a, b| (a < b) as i64 as f64,
+            ">" => |a, b| (a > b) as i64 as f64,
+            "<=" => |a, b| (a <= b) as i64 as f64,
+            ">=" => |a, b| (a >= b) as i64 as f64,
+            "==" => |a, b| (a == b) as i64 as f64,
+            "!=" => |a, b| (a != b) as i64 as f64,
+            ")" => return Ok(left),
+            _ => return Err(op.error(format!("unknown operator {}", op.text))),
+        };
+        self.position += 1;
+        let right = self.expression()?;
+        Ok(apply(left, right))
+    }
+
+    fn term(&mut self) -> Result<f64, AssembleError> {
+        let token = self.next()?.clone();
+        let unary: Option<fn(f64) -> f64> = match token.text.as_str() {
+            "-" => Some(|a| -a),
+            "~" => Some(|a| !(a as i64) as f64),
+            "!" => Some(|a| (a == 0.0) as i64 as f64),
+            "floor" => Some(f64::floor),
+            "ceil" => Some(f64::ceil),
+            "abs" => Some(f64::abs),
+            "sqrt" => Some(f64::sqrt),
+            "sin" => Some(f64::sin),
+            "cos" => Some(f64::cos),
+            "tan" => Some(f64::tan),
+            "exp" => Some(f64::exp),
+            "log" => Some(f64::ln),
+            "sign" => Some(f64::signum),
+            _ => None,
+        };
+        if let Some(unary) = unary {
+            return Ok(unary(self.term()?));
+        }
+        match token.text.as_str() {
+            "(" => {
+                let value = self.expression()?;
+                let close = self.next()?;
+                if close.text != ")" {
+                    return Err(close.error("expected )"));
+                }
+                Ok(value)
+            }
+            // the byte already assembled at an address
+            "@" => {
+                let address = self.term()? as usize;
+                Ok(address
+                    .checked_sub(START_ADDR)
+                    .and_then(|index| self.assembler.rom.get(index))
+                    .copied()
+                    .unwrap_or(0) as f64)
+            }
+            "HERE" => Ok(self.assembler.here as f64),
+            "PI" => Ok(std::f64::consts::PI),
+            "E" => Ok(std::f64::consts::E),
+            _ => {
+                if let Some(register) = self.assembler.register_of(&token) {
+                    return Ok(register as f64);
+                }
+                if let Some(&value) = self.assembler.constants.get(&token.text) {
+                    return Ok(value);
+                }
+                self.assembler
+                    .known_value(&token)
+                    .map(|value| value as f64)
+                    .ok_or_else(|| token.error(format!("undefined name {}", token.text)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disasm::{Disassembly, Syntax};
+    use crate::Variant;
+
+    #[test]
+    fn round_trips_through_the_disassembler() {
+        let source = "\
+: main
+  v0 := 0x05
+  v1 += v0
+  i := 0x300
+  sprite v0 v1 5
+  return
+";
+        let assembly = assemble(source).unwrap();
+        let listing = Disassembly::new(&assembly.rom, Variant::Chip8).render(Syntax::Octo, false);
+        let listed: Vec<&str> = listing.lines().map(str::trim).collect();
+        let expected: Vec<&str> = source.lines().skip(1).map(str::trim).collect();
+        assert_eq!(listed, expected);
+    }
+
+    #[test]
+    fn resolves_forward_labels() {
+        let assembly = assemble(": main\n  jump done\n  clear\n: done\n  return\n").unwrap();
+        assert_eq!(assembly.rom, [0x12, 0x04, 0x00, 0xE0, 0x00, 0xEE]);
+        assert_eq!(assembly.labels["done"], 0x204);
+    }
+
+    #[test]
+    fn rejects_out_of_range_immediates() {
+        let error = assemble(": main\n  v0 := 256\n").unwrap_err();
+        assert_eq!((error.line, error.column), (2, 9));
+    }
+
+    #[test]
+    fn long_load_is_four_bytes() {
+        let assembly = assemble(": main\n  i := long 0x1234\n  clear\n").unwrap();
+        assert_eq!(assembly.rom, [0xF0, 0x00, 0x12, 0x34, 0x00, 0xE0]);
+    }
+
+    #[test]
+    fn rejects_comparisons_with_vf() {
+        for source in [
+            ": main\n  if vf < v1 then clear\n",
+            ": main\n  if v1 >= vf then clear\n",
+        ] {
+            let error = assemble(source).unwrap_err();
+            assert_eq!((error.line, error.column), (2, 3));
+        }
+        assert!(assemble(": main\n  if vf == v1 then clear\n").is_ok());
+    }
+}
@@ -0,0 +1,134 @@
+//! Rendering the buzzer to PCM, for WAV export and for the SDL buzzer.
+//!
+//! The sound timer only changes at 60Hz, so rendering is done a frame at a time: after every
+//! frame `AudioRenderer::render_frame` adds 1/60th of a second of tone or silence depending on
+//! whether the sound timer is still running.
+
+use std::io::{self, Write};
+
+use crate::{audio_pattern_rate, Emulator, AUDIO_PATTERN_SIZE};
+
+pub const SAMPLE_RATE: u32 = 44100;
+/// samples in one 60Hz frame
+pub const SAMPLES_PER_FRAME: usize = SAMPLE_RATE as usize / 60;
+const TONE_FREQ_HZ: f32 = 250.0;
+const VOLUME: f32 = 0.25;
+
+/// The buzzer's waveform: a square wave, or the XO-CHIP audio pattern once a program sets one.
+pub(crate) struct Tone {
+    sample_rate: f32,
+    phase_inc: f32,
+    phase: f32,
+    pattern: Option<[u8; AUDIO_PATTERN_SIZE]>,
+    pattern_inc: f32,
+    pattern_phase: f32,
+}
+
+impl Tone {
+    pub(crate) fn new(sample_rate: f32) -> Self {
+        Self {
+            sample_rate,
+            phase_inc: TONE_FREQ_HZ / sample_rate,
+            phase: 0.0,
+            pattern: None,
+            pattern_inc: 0.0,
+            pattern_phase: 0.0,
+        }
+    }
+
+    pub(crate) fn set_pattern(&mut self, pattern: &[u8; AUDIO_PATTERN_SIZE], pitch: u8) {
+        self.pattern_inc = audio_pattern_rate(pitch) / self.sample_rate;
+        self.pattern = Some(*pattern);
+    }
+
+    /// Next sample, between -`VOLUME` and `VOLUME`.
+    pub(crate) fn next_sample(&mut self) -> f32 {
+        let high = match &self.pattern {
+            Some(pattern) => {
+                // step through the 128 bits of the pattern, most significant bit first
+                let bit = self.pattern_phase as usize;
+                self.pattern_phase =
+                    (self.pattern_phase + self.pattern_inc) % (AUDIO_PATTERN_SIZE * 8) as f32;
+                pattern[bit / 8] & (0x80 >> (bit % 8)) != 0
+            }
+            None => {
+                let high = self.phase <= 0.5;
+                self.phase = (self.phase + self.phase_inc) % 1.0;
+                high
+            }
+        };
+        if high {
+            VOLUME
+        } else {
+            -VOLUME
+        }
+    }
+}
+
+/// Collects the sound of a session as 16 bit mono samples at `SAMPLE_RATE`.
+pub struct AudioRenderer {
+    tone: Tone,
+    samples: Vec<i16>,
+}
+
+impl Default for AudioRenderer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AudioRenderer {
+    pub fn new() -> Self {
+        Self {
+            tone: Tone::new(SAMPLE_RATE as f32),
+            samples: Vec::new(),
+        }
+    }
+
+    /// Call after every frame to add that frame's sound.
+    pub fn render_frame(&mut self, emulator: &Emulator) {
+        if let Some(pattern) = emulator.audio_pattern() {
+            self.tone.set_pattern(pattern, emulator.pitch());
+        }
+        if emulator.sound_status() {
+            for _ in 0..SAMPLES_PER_FRAME {
+                let sample = self.tone.next_sample();
+                self.samples.push((sample * i16::MAX as f32) as i16);
+            }
+        } else {
+            // the wave carries on from where it stopped next time, like the SDL device
+            self.samples
+                .extend(std::iter::repeat_n(0, SAMPLES_PER_FRAME));
+        }
+    }
+
+    pub fn samples(&self) -> &[i16] {
+        &self.samples
+    }
+
+    /// Writes everything rendered so far as a PCM WAV file.
+    pub fn write_wav<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let data_size = (self.samples.len() * 2) as u32;
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&(36 + data_size).to_le_bytes())?;
+        writer.write_all(b"WAVE")?;
+        // format chunk: PCM, mono, 16 bits per sample
+        writer.write_all(b"fmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?;
+        writer.write_all(&1u16.to_le_bytes())?;
+        writer.write_all(&1u16.to_le_bytes())?;
+        writer.write_all(&SAMPLE_RATE.to_le_bytes())?;
+        writer.write_all(&(SAMPLE_RATE * 2).to_le_bytes())?;
+        writer.write_all(&2u16.to_le_bytes())?;
+        writer.write_all(&16u16.to_le_bytes())?;
+        writer.write_all(b"data")?;
+        writer.write_all(&data_size.to_le_bytes())?;
+        let data: Vec<u8> = self
+            .samples
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect();
+        writer.write_all(&data)?;
+        writer.flush()
+    }
+}
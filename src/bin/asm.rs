@@ -0,0 +1,79 @@
+//! Assembles Octo source into a ROM file, see the `assembler` module.
+
+use chip_eight_emu::assembler;
+use std::env;
+use std::fs;
+use std::process;
+
+const USAGE: &str = "<source> [--output <rom>] [--symbols <file>]";
+
+struct Options {
+    source_path: String,
+    output_path: String,
+    symbols_path: Option<String>,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut source_path = None;
+    let mut output_path = None;
+    let mut symbols_path = None;
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--output" | "-o" => {
+                output_path = Some(args.next().ok_or("--output needs a file")?.clone());
+            }
+            "--symbols" => {
+                symbols_path = Some(args.next().ok_or("--symbols needs a file")?.clone());
+            }
+            _ if source_path.is_none() && !arg.starts_with('-') => source_path = Some(arg.clone()),
+            _ => return Err(format!("Unexpected argument: {}", arg)),
+        }
+    }
+    let source_path: String = source_path.ok_or("No source file given")?;
+    // foo.8o assembles to foo.ch8 unless told otherwise
+    let output_path = output_path.unwrap_or_else(|| {
+        let stem = source_path.strip_suffix(".8o").unwrap_or(&source_path);
+        format!("{}.ch8", stem)
+    });
+    Ok(Options {
+        source_path,
+        output_path,
+        symbols_path,
+    })
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\nUsage: {} {}", message, args[0], USAGE);
+            process::exit(2);
+        }
+    };
+    let source = match fs::read_to_string(&options.source_path) {
+        Ok(source) => source,
+        Err(error) => {
+            eprintln!("Unable to read {}: {}", options.source_path, error);
+            process::exit(2);
+        }
+    };
+    let assembly = match assembler::assemble(&source) {
+        Ok(assembly) => assembly,
+        Err(error) => {
+            eprintln!("{}: {}", options.source_path, error);
+            process::exit(1);
+        }
+    };
+    if let Err(error) = fs::write(&options.output_path, &assembly.rom) {
+        eprintln!("Unable to write {}: {}", options.output_path, error);
+        process::exit(2);
+    }
+    if let Some(path) = &options.symbols_path {
+        if let Err(error) = fs::write(path, assembly.symbol_file()) {
+            eprintln!("Unable to write {}: {}", path, error);
+            process::exit(2);
+        }
+    }
+}
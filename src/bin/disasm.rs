@@ -0,0 +1,76 @@
+//! Prints a disassembly of a ROM file, see the `disasm` module.
+
+use chip_eight_emu::disasm::{Disassembly, Syntax};
+use chip_eight_emu::{EmuError, Variant};
+use std::env;
+use std::fs;
+use std::process;
+
+const USAGE: &str =
+    "<file> [--variant <chip8|schip|xo-chip>] [--syntax <classic|octo>] [--no-columns]";
+
+struct Options {
+    rom_path: String,
+    variant: Variant,
+    syntax: Syntax,
+    columns: bool,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut rom_path = None;
+    let mut variant = Variant::default();
+    let mut syntax = Syntax::default();
+    let mut columns = true;
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--variant" => {
+                let name = args.next().ok_or("--variant needs a name")?;
+                variant = Variant::from_name(name).ok_or(format!("Unknown variant: {}", name))?;
+            }
+            "--syntax" => {
+                let name = args.next().ok_or("--syntax needs a name")?;
+                syntax = Syntax::from_name(name).ok_or(format!("Unknown syntax: {}", name))?;
+            }
+            "--no-columns" => columns = false,
+            _ if rom_path.is_none() && !arg.starts_with("--") => rom_path = Some(arg.clone()),
+            _ => return Err(format!("Unexpected argument: {}", arg)),
+        }
+    }
+    Ok(Options {
+        rom_path: rom_path.ok_or("No ROM file given")?,
+        variant,
+        syntax,
+        columns,
+    })
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\nUsage: {} {}", message, args[0], USAGE);
+            process::exit(2);
+        }
+    };
+    let rom = match fs::read(&options.rom_path) {
+        Ok(rom) => rom,
+        Err(error) => {
+            eprintln!("Unable to read {}: {}", options.rom_path, error);
+            process::exit(2);
+        }
+    };
+    // the same limit the emulator puts on loading it
+    let max = options.variant.ram_size() - 0x200;
+    if rom.len() > max {
+        let error = EmuError::RomTooLarge {
+            size: rom.len(),
+            max,
+        };
+        eprintln!("Unable to disassemble {}: {}", options.rom_path, error);
+        process::exit(2);
+    }
+    let disassembly = Disassembly::new(&rom, options.variant);
+    print!("{}", disassembly.render(options.syntax, options.columns));
+}
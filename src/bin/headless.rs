@@ -0,0 +1,678 @@
+//! Runs a ROM without a window or audio, for CI and batch testing.
+//!
+//! The emulator runs as fast as it can for a number of frames, or until the program reaches an
+//! address or puts a given picture on screen, with key presses scripted from the command line or
+//! taken from a movie. Afterwards the screen, registers and RAM can be dumped, and the whole run
+//! can be recorded as a GIF or a raw video stream, and its sound as a WAV file. `--trace` logs
+//! every instruction, or with `--trace-last` only the last few before the run ended.
+//! `--profile` and `--profile-folded` write a profile of the run and its flamegraph stacks, and
+//! `--coverage` a report of which ROM bytes ran or were read. `--heatmap` saves a PNG of the RAM
+//! accesses near the end of the run.
+//!
+//! With `--gdb <port>` it waits for GDB to attach before running, and frames only count while GDB
+//! lets the emulator run.
+//!
+//! Exit codes: 0 when the run finished, 1 when the emulator faulted or a movie desynced,
+//! 2 for bad arguments or files, 3 when an `--until` condition was never met.
+
+use chip_eight_emu::audio::AudioRenderer;
+use chip_eight_emu::coverage::Coverage;
+use chip_eight_emu::gdb::GdbServer;
+use chip_eight_emu::heatmap::Heatmap;
+use chip_eight_emu::movie::{Movie, MoviePlayer};
+use chip_eight_emu::profile::Profiler;
+use chip_eight_emu::trace::{TraceFilter, TraceFormat, Tracer};
+use chip_eight_emu::video::{self, GifRecorder, RawRecorder};
+use chip_eight_emu::*;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+const DEFAULT_FRAMES: usize = 600;
+// how long a scripted key is held when no duration is given
+const DEFAULT_KEY_FRAMES: usize = 5;
+// how often a halted emulator checks for GDB packets
+const GDB_POLL_INTERVAL: Duration = Duration::from_millis(5);
+// pixels per heatmap cell, making a 256x256 image
+const HEATMAP_SCALE: usize = 4;
+const USAGE: &str = "<file> [--quirks <preset>] [--variant <chip8|schip|xo-chip>] [--seed <n>] \
+                     [--frames <n>] [--until-pc <addr>] [--until-screen <hash>] \
+                     [--key <frame>:<key>[:<frames>]]... [--play <movie>] \
+                     [--screen <ascii|hash|pbm|png|none>] [--scale <n>] [--output <file>] [--registers] [--ram <file>] \
+                     [--gif <file>] [--raw-video <file|->] [--wav <file>] [--gdb <port>] \
+                     [--trace <file|->] [--trace-format <human|json>] [--trace-range <start>-<end>] \
+                     [--trace-opcodes <digits>] [--trace-last <n>] [--profile <file>] [--profile-folded <file>] \
+                     [--coverage <file>] [--heatmap <file>]";
+// characters for each color, plane 0 | plane 1 << 1
+const ASCII_PIXELS: [char; 4] = ['.', '#', '+', '@'];
+
+const EXIT_FAULT: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_NOT_REACHED: i32 = 3;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ScreenFormat {
+    Ascii,
+    Hash,
+    Pbm,
+    Png,
+    None,
+}
+
+/// A key held down for `frames` frames starting at `start`.
+struct ScriptedKey {
+    start: usize,
+    key: usize,
+    frames: usize,
+}
+
+struct Options {
+    rom_path: String,
+    config: Config,
+    frames: Option<usize>,
+    until_pc: Option<u16>,
+    until_screen: Option<u64>,
+    keys: Vec<ScriptedKey>,
+    play_path: Option<String>,
+    screen: ScreenFormat,
+    scale: usize,
+    output_path: Option<String>,
+    registers: bool,
+    ram_path: Option<String>,
+    gif_path: Option<String>,
+    raw_video_path: Option<String>,
+    wav_path: Option<String>,
+    gdb_port: Option<u16>,
+    trace_path: Option<String>,
+    trace_format: TraceFormat,
+    trace_filter: TraceFilter,
+    trace_last: Option<usize>,
+    profile_path: Option<String>,
+    profile_folded_path: Option<String>,
+    coverage_path: Option<String>,
+    heatmap_path: Option<String>,
+}
+
+fn parse_key(text: &str) -> Result<ScriptedKey, String> {
+    let invalid = || {
+        format!(
+            "Invalid key press: {} (expected <frame>:<key>[:<frames>])",
+            text
+        )
+    };
+    let mut parts = text.split(':');
+    let start = parts
+        .next()
+        .and_then(|frame| frame.parse().ok())
+        .ok_or_else(invalid)?;
+    let key = parts
+        .next()
+        .and_then(|key| usize::from_str_radix(key, 16).ok())
+        .filter(|&key| key < 16)
+        .ok_or_else(invalid)?;
+    let frames = match parts.next() {
+        Some(frames) => frames.parse().map_err(|_| invalid())?,
+        None => DEFAULT_KEY_FRAMES,
+    };
+    if parts.next().is_some() {
+        return Err(invalid());
+    }
+    Ok(ScriptedKey { start, key, frames })
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut rom_path = None;
+    let mut options = Options {
+        rom_path: String::new(),
+        config: Config::default(),
+        frames: None,
+        until_pc: None,
+        until_screen: None,
+        keys: Vec::new(),
+        play_path: None,
+        screen: ScreenFormat::Ascii,
+        scale: 1,
+        output_path: None,
+        registers: false,
+        ram_path: None,
+        gif_path: None,
+        raw_video_path: None,
+        wav_path: None,
+        gdb_port: None,
+        trace_path: None,
+        trace_format: TraceFormat::default(),
+        trace_filter: TraceFilter::default(),
+        trace_last: None,
+        profile_path: None,
+        profile_folded_path: None,
+        coverage_path: None,
+        heatmap_path: None,
+    };
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--quirks" => {
+                let name = args.next().ok_or("--quirks needs a preset")?;
+                options.config.quirks = Quirks::from_name(name).ok_or(format!(
+                    "Unknown quirks preset: {} (presets: vip, chip48, schip1.1, schip, xo-chip, default)",
+                    name
+                ))?;
+            }
+            "--variant" => {
+                let name = args.next().ok_or("--variant needs a name")?;
+                options.config.variant =
+                    Variant::from_name(name).ok_or(format!("Unknown variant: {}", name))?;
+            }
+            "--seed" => {
+                let seed = args.next().ok_or("--seed needs a number")?;
+                options.config.seed =
+                    Some(parse_number(seed).ok_or(format!("Invalid seed: {}", seed))?);
+            }
+            "--frames" => {
+                let frames = args.next().ok_or("--frames needs a number")?;
+                options.frames = Some(
+                    frames
+                        .parse()
+                        .map_err(|_| format!("Invalid number of frames: {}", frames))?,
+                );
+            }
+            "--until-pc" => {
+                let address = args.next().ok_or("--until-pc needs an address")?;
+                options.until_pc = Some(
+                    parse_number(address)
+                        .and_then(|address| u16::try_from(address).ok())
+                        .ok_or(format!("Invalid address: {}", address))?,
+                );
+            }
+            "--until-screen" => {
+                let hash = args.next().ok_or("--until-screen needs a screen hash")?;
+                let digits = hash.strip_prefix("0x").unwrap_or(hash);
+                options.until_screen = Some(
+                    u64::from_str_radix(digits, 16)
+                        .map_err(|_| format!("Invalid screen hash: {}", hash))?,
+                );
+            }
+            "--key" => {
+                let key = args.next().ok_or("--key needs a key press")?;
+                options.keys.push(parse_key(key)?);
+            }
+            "--play" => {
+                options.play_path = Some(args.next().ok_or("--play needs a file")?.clone());
+            }
+            "--screen" => {
+                let format = args.next().ok_or("--screen needs a format")?;
+                options.screen = match format.as_str() {
+                    "ascii" => ScreenFormat::Ascii,
+                    "hash" => ScreenFormat::Hash,
+                    "pbm" => ScreenFormat::Pbm,
+                    "png" => ScreenFormat::Png,
+                    "none" => ScreenFormat::None,
+                    _ => return Err(format!("Unknown screen format: {}", format)),
+                };
+            }
+            "--scale" => {
+                let scale = args.next().ok_or("--scale needs a number")?;
+                options.scale = scale
+                    .parse()
+                    .ok()
+                    .filter(|&scale| scale > 0)
+                    .ok_or(format!("Invalid scale: {}", scale))?;
+            }
+            "--output" => {
+                options.output_path = Some(args.next().ok_or("--output needs a file")?.clone());
+            }
+            "--registers" => options.registers = true,
+            "--ram" => {
+                options.ram_path = Some(args.next().ok_or("--ram needs a file")?.clone());
+            }
+            "--gif" => {
+                options.gif_path = Some(args.next().ok_or("--gif needs a file")?.clone());
+            }
+            "--raw-video" => {
+                options.raw_video_path =
+                    Some(args.next().ok_or("--raw-video needs a file or -")?.clone());
+            }
+            "--wav" => {
+                options.wav_path = Some(args.next().ok_or("--wav needs a file")?.clone());
+            }
+            "--gdb" => {
+                let port = args.next().ok_or("--gdb needs a port")?;
+                options.gdb_port = Some(
+                    port.parse()
+                        .map_err(|_| format!("Invalid port: {}", port))?,
+                );
+            }
+            "--trace" => {
+                options.trace_path = Some(args.next().ok_or("--trace needs a file or -")?.clone());
+            }
+            "--trace-format" => {
+                let name = args.next().ok_or("--trace-format needs a format")?;
+                options.trace_format = TraceFormat::from_name(name)
+                    .ok_or(format!("Unknown trace format: {}", name))?;
+            }
+            "--trace-range" => {
+                let range = args.next().ok_or("--trace-range needs a range")?;
+                options.trace_filter.addresses = Some(parse_range(range)?);
+            }
+            "--trace-opcodes" => {
+                let classes = args.next().ok_or("--trace-opcodes needs opcode digits")?;
+                options.trace_filter.opcode_classes = parse_opcode_classes(classes)?;
+            }
+            "--trace-last" => {
+                let count = args.next().ok_or("--trace-last needs a number")?;
+                options.trace_last = Some(
+                    count
+                        .parse()
+                        .map_err(|_| format!("Invalid number of instructions: {}", count))?,
+                );
+            }
+            "--profile" => {
+                options.profile_path = Some(args.next().ok_or("--profile needs a file")?.clone());
+            }
+            "--profile-folded" => {
+                options.profile_folded_path =
+                    Some(args.next().ok_or("--profile-folded needs a file")?.clone());
+            }
+            "--coverage" => {
+                options.coverage_path = Some(args.next().ok_or("--coverage needs a file")?.clone());
+            }
+            "--heatmap" => {
+                options.heatmap_path = Some(args.next().ok_or("--heatmap needs a file")?.clone());
+            }
+            _ if rom_path.is_none() && !arg.starts_with("--") => rom_path = Some(arg.clone()),
+            _ => return Err(format!("Unexpected argument: {}", arg)),
+        }
+    }
+    options.rom_path = rom_path.ok_or("No ROM file given")?;
+    if options.play_path.is_some() && !options.keys.is_empty() {
+        return Err("Can't script keys while playing a movie".to_string());
+    }
+    // GDB can stop the emulator anywhere, which neither of these can cope with
+    if options.gdb_port.is_some() && (options.play_path.is_some() || options.until_pc.is_some()) {
+        return Err("--gdb can't be used with --play or --until-pc".to_string());
+    }
+    if options.raw_video_path.as_deref() == Some("-")
+        && options.output_path.is_none()
+        && options.screen != ScreenFormat::None
+    {
+        return Err("Raw video on stdout needs --output or --screen none".to_string());
+    }
+    if options.trace_path.as_deref() == Some("-")
+        && (options.raw_video_path.as_deref() == Some("-")
+            || (options.output_path.is_none() && options.screen != ScreenFormat::None))
+    {
+        return Err("A trace on stdout needs the screen and video to go elsewhere".to_string());
+    }
+    // the register dump always goes to stdout
+    if options.registers
+        && (options.raw_video_path.as_deref() == Some("-")
+            || options.trace_path.as_deref() == Some("-"))
+    {
+        return Err("--registers can't be used with raw video or a trace on stdout".to_string());
+    }
+    if options.trace_path.is_none()
+        && (options.trace_last.is_some()
+            || options.trace_filter != TraceFilter::default()
+            || options.trace_format != TraceFormat::default())
+    {
+        return Err("The trace options need --trace".to_string());
+    }
+    Ok(options)
+}
+
+/// Parses an inclusive address range like `0x200-0x2ff`.
+fn parse_range(text: &str) -> Result<std::ops::RangeInclusive<u16>, String> {
+    let invalid = || format!("Invalid address range: {} (expected <start>-<end>)", text);
+    let (start, end) = text.split_once('-').ok_or_else(invalid)?;
+    let address = |text| {
+        parse_number(text)
+            .and_then(|address| u16::try_from(address).ok())
+            .ok_or_else(invalid)
+    };
+    Ok(address(start)?..=address(end)?)
+}
+
+/// Parses opcode classes given as their first hex digits, like `D,8` or `D8`.
+fn parse_opcode_classes(text: &str) -> Result<u16, String> {
+    text.chars()
+        .filter(|&c| c != ',')
+        .try_fold(0, |classes, digit| {
+            let digit = digit
+                .to_digit(16)
+                .ok_or(format!("Invalid opcode class: {}", digit))?;
+            Ok(classes | (1 << digit))
+        })
+}
+
+/// Keypad bitmask for a frame of the key script.
+fn scripted_keys(keys: &[ScriptedKey], frame: usize) -> u16 {
+    keys.iter()
+        .filter(|key| (key.start..key.start.saturating_add(key.frames)).contains(&frame))
+        .fold(0, |mask, key| mask | (1 << key.key))
+}
+
+/// Runs a frame like `Emulator::run_frame`, checking the program counter before every
+/// instruction. Returns true, leaving the rest of the frame unrun, once it hits `until_pc`.
+fn run_frame(emulator: &mut Emulator, until_pc: Option<u16>) -> Result<bool, EmuError> {
+    let Some(until_pc) = until_pc else {
+        emulator.run_frame()?;
+        return Ok(false);
+    };
+    for _ in 0..emulator.config().ticks_per_frame {
+        if emulator.registers().program_counter == until_pc {
+            return Ok(true);
+        }
+        emulator.tick()?;
+    }
+    emulator.tick_timers();
+    Ok(false)
+}
+
+fn screen_hash(emulator: &Emulator) -> u64 {
+    state::hash(&emulator.pixel_colors())
+}
+
+fn format_screen(emulator: &Emulator, format: ScreenFormat, scale: usize) -> Vec<u8> {
+    match format {
+        ScreenFormat::Ascii => {
+            let width = emulator.display_width();
+            let mut text = String::new();
+            for row in emulator.pixel_colors().chunks(width) {
+                text.extend(row.iter().map(|&color| ASCII_PIXELS[color as usize]));
+                text.push('\n');
+            }
+            text.into_bytes()
+        }
+        ScreenFormat::Hash => format!("{:016x}\n", screen_hash(emulator)).into_bytes(),
+        ScreenFormat::Pbm => screenshot::pbm(emulator).into_bytes(),
+        ScreenFormat::Png => screenshot::png(emulator, scale),
+        ScreenFormat::None => Vec::new(),
+    }
+}
+
+fn format_registers(emulator: &Emulator) -> String {
+    let registers = emulator.registers();
+    let mut text = registers
+        .v
+        .iter()
+        .enumerate()
+        .map(|(index, value)| format!("V{:X}={:02X}", index, value))
+        .collect::<Vec<_>>()
+        .join(" ");
+    text.push_str(&format!(
+        "\nPC={:04X} I={:04X} SP={} DT={:02X} ST={:02X}\n",
+        registers.program_counter,
+        registers.i,
+        registers.stack_pointer,
+        registers.delay_timer,
+        registers.sound_timer
+    ));
+    text
+}
+
+fn exit_with(message: String, code: i32) -> ! {
+    eprintln!("{}", message);
+    process::exit(code);
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let options = parse_args(&args).unwrap_or_else(|message| {
+        exit_with(
+            format!("{}\nUsage: {} {}", message, args[0], USAGE),
+            EXIT_USAGE,
+        )
+    });
+
+    let rom = fs::read(&options.rom_path).unwrap_or_else(|error| {
+        exit_with(
+            format!("Unable to read {}: {}", options.rom_path, error),
+            EXIT_USAGE,
+        )
+    });
+
+    // a movie brings its own settings and decides how many frames to run
+    let mut player = None;
+    let mut emulator = match &options.play_path {
+        Some(path) => {
+            let movie = fs::read(path)
+                .map_err(|error| error.to_string())
+                .and_then(|data| Movie::from_bytes(&data).map_err(|error| error.to_string()));
+            let movie = movie.unwrap_or_else(|error| {
+                exit_with(format!("Unable to play {}: {}", path, error), EXIT_USAGE)
+            });
+            let emulator = movie.create_emulator(&rom).unwrap_or_else(|error| {
+                exit_with(format!("Unable to play {}: {}", path, error), EXIT_USAGE)
+            });
+            player = Some(MoviePlayer::new(movie));
+            emulator
+        }
+        None => {
+            let mut emulator = Emulator::with_config(options.config);
+            if let Err(error) = emulator.load(&rom) {
+                exit_with(
+                    format!("Unable to load {}: {}", options.rom_path, error),
+                    EXIT_USAGE,
+                );
+            }
+            emulator
+        }
+    };
+    let frames = options.frames.unwrap_or(match &player {
+        Some(player) => player.movie().frames.len(),
+        None => DEFAULT_FRAMES,
+    });
+    let mut gif = options.gif_path.as_ref().map(|path| {
+        File::create(path)
+            .and_then(|file| GifRecorder::new(BufWriter::new(file), options.scale))
+            .unwrap_or_else(|error| {
+                exit_with(format!("Unable to create {}: {}", path, error), EXIT_USAGE)
+            })
+    });
+    let mut raw_video = options.raw_video_path.as_ref().map(|path| {
+        let writer: Box<dyn Write> = if path == "-" {
+            Box::new(io::stdout().lock())
+        } else {
+            let file = File::create(path).unwrap_or_else(|error| {
+                exit_with(format!("Unable to create {}: {}", path, error), EXIT_USAGE)
+            });
+            Box::new(BufWriter::new(file))
+        };
+        let (width, height) = video::frame_size(options.scale);
+        eprintln!(
+            "Raw video is rgb24 {}x{} at 60 frames a second",
+            width, height
+        );
+        RawRecorder::new(writer, options.scale)
+    });
+    let mut audio = options.wav_path.as_ref().map(|_| AudioRenderer::new());
+    if let Some(path) = &options.trace_path {
+        let writer: Box<dyn Write> = if path == "-" {
+            Box::new(io::stdout())
+        } else {
+            let file = File::create(path).unwrap_or_else(|error| {
+                exit_with(format!("Unable to create {}: {}", path, error), EXIT_USAGE)
+            });
+            Box::new(BufWriter::new(file))
+        };
+        let mut tracer =
+            Tracer::new(writer, options.trace_format).with_filter(options.trace_filter.clone());
+        if let Some(count) = options.trace_last {
+            tracer = tracer.keep_last(count);
+        }
+        emulator.set_tracer(Some(tracer));
+    }
+    if options.profile_path.is_some() || options.profile_folded_path.is_some() {
+        emulator.set_profiler(Some(Profiler::new(emulator.config().variant)));
+    }
+    if options.coverage_path.is_some() {
+        emulator.set_coverage(Some(Coverage::new(&rom, emulator.config().variant)));
+    }
+    if options.heatmap_path.is_some() {
+        emulator.set_heatmap(Some(Heatmap::new()));
+    }
+    let waiting = options.until_pc.is_some() || options.until_screen.is_some();
+    let mut gdb = options.gdb_port.map(|port| {
+        let mut server = GdbServer::bind(("127.0.0.1", port), &emulator).unwrap_or_else(|error| {
+            exit_with(
+                format!("Unable to listen on port {}: {}", port, error),
+                EXIT_USAGE,
+            )
+        });
+        eprintln!("Waiting for GDB on port {}", port);
+        if let Err(error) = server.wait_for_client() {
+            exit_with(format!("Unable to accept GDB: {}", error), EXIT_USAGE);
+        }
+        server
+    });
+
+    let mut frame = 0;
+    let mut reached = false;
+    let mut fault = None;
+    while frame < frames && !reached {
+        match &mut player {
+            Some(player) => {
+                // past the end of the movie the keypad stays as the movie left it
+                player.apply_frame(&mut emulator);
+            }
+            None => emulator.set_pressed_keys(scripted_keys(&options.keys, frame)),
+        }
+        if let Some(gdb) = &mut gdb {
+            let before = gdb.frame();
+            if let Err(error) = gdb.run_frame(&mut emulator) {
+                fault = Some(error);
+                break;
+            }
+            if gdb.frame() == before {
+                // stopped by GDB, or still part way through the frame
+                if gdb.is_halted() {
+                    thread::sleep(GDB_POLL_INTERVAL);
+                }
+                continue;
+            }
+        } else {
+            match run_frame(&mut emulator, options.until_pc) {
+                Ok(hit) => reached = hit,
+                Err(error) => {
+                    fault = Some(error);
+                    break;
+                }
+            }
+        }
+        frame += 1;
+        if let Some(audio) = &mut audio {
+            audio.render_frame(&emulator);
+        }
+        if let Some(gif) = &mut gif {
+            if let Err(error) = gif.capture(&emulator) {
+                exit_with(format!("Unable to write the GIF: {}", error), EXIT_USAGE);
+            }
+        }
+        if let Some(raw_video) = &mut raw_video {
+            if let Err(error) = raw_video.capture(&emulator) {
+                exit_with(format!("Unable to write the video: {}", error), EXIT_USAGE);
+            }
+        }
+        if options.until_screen == Some(screen_hash(&emulator)) {
+            reached = true;
+        }
+    }
+
+    if let Some(gif) = gif {
+        if let Err(error) = gif.finish().and_then(|mut writer| writer.flush()) {
+            exit_with(format!("Unable to write the GIF: {}", error), EXIT_USAGE);
+        }
+    }
+    if let Some(raw_video) = raw_video {
+        if let Err(error) = raw_video.finish() {
+            exit_with(format!("Unable to write the video: {}", error), EXIT_USAGE);
+        }
+    }
+    if let Some(tracer) = emulator.take_tracer() {
+        if let Err(error) = tracer.finish() {
+            exit_with(format!("Unable to write the trace: {}", error), EXIT_USAGE);
+        }
+    }
+    if let Some(profiler) = emulator.take_profiler() {
+        let outputs = [
+            (&options.profile_path, profiler.report()),
+            (&options.profile_folded_path, profiler.folded_stacks()),
+        ];
+        for (path, text) in outputs {
+            if let Some(path) = path {
+                if let Err(error) = fs::write(path, text) {
+                    exit_with(format!("Unable to write {}: {}", path, error), EXIT_USAGE);
+                }
+            }
+        }
+    }
+    if let (Some(coverage), Some(path)) = (emulator.take_coverage(), &options.coverage_path) {
+        if let Err(error) = fs::write(path, coverage.report()) {
+            exit_with(format!("Unable to write {}: {}", path, error), EXIT_USAGE);
+        }
+    }
+    if let (Some(heatmap), Some(path)) = (emulator.take_heatmap(), &options.heatmap_path) {
+        if let Err(error) = heatmap.save(path, HEATMAP_SCALE) {
+            exit_with(format!("Unable to write {}: {}", path, error), EXIT_USAGE);
+        }
+    }
+    if let (Some(audio), Some(path)) = (audio, &options.wav_path) {
+        if let Err(error) =
+            File::create(path).and_then(|file| audio.write_wav(BufWriter::new(file)))
+        {
+            exit_with(format!("Unable to write {}: {}", path, error), EXIT_USAGE);
+        }
+    }
+
+    let mut code = 0;
+    match fault {
+        // the program asked to stop, which is a normal way for a test ROM to finish
+        Some(EmuError::Exited { address }) => {
+            eprintln!("Program exited at {:03X} after {} frames", address, frame)
+        }
+        Some(error) => {
+            eprintln!("Emulator stopped after {} frames: {}", frame, error);
+            code = EXIT_FAULT;
+        }
+        None if waiting && !reached => {
+            eprintln!("Condition not reached after {} frames", frame);
+            code = EXIT_NOT_REACHED;
+        }
+        None => eprintln!("Ran {} frames", frame),
+    }
+    if let Some(player) = &player {
+        // the final state is only comparable when exactly the recorded frames were run
+        if fault.is_none() && frame == player.movie().frames.len() {
+            if let Err(error) = player.movie().verify(&emulator) {
+                eprintln!("{}", error);
+                code = EXIT_FAULT;
+            }
+        }
+    }
+
+    let screen = format_screen(&emulator, options.screen, options.scale);
+    match &options.output_path {
+        Some(path) => {
+            if let Err(error) = fs::write(path, &screen) {
+                exit_with(format!("Unable to write {}: {}", path, error), EXIT_USAGE);
+            }
+        }
+        None => {
+            if let Err(error) = io::stdout().write_all(&screen) {
+                exit_with(format!("Unable to write the screen: {}", error), EXIT_USAGE);
+            }
+        }
+    }
+    if options.registers {
+        print!("{}", format_registers(&emulator));
+    }
+    if let Some(path) = &options.ram_path {
+        if let Err(error) = fs::write(path, emulator.ram()) {
+            exit_with(format!("Unable to write {}: {}", path, error), EXIT_USAGE);
+        }
+    }
+    process::exit(code);
+}
@@ -0,0 +1,100 @@
+//! Debugs a ROM in the terminal, see `chip_eight_emu::tui` for what's on screen and `help` once
+//! it's running for the commands.
+//!
+//! The program starts out paused, unless `--run` is given. `--break` sets breakpoints before it
+//! starts, any number of them.
+
+use chip_eight_emu::tui::Tui;
+use chip_eight_emu::*;
+use std::env;
+use std::fs;
+use std::process;
+
+const USAGE: &str = "<file> [--quirks <preset>] [--variant <chip8|schip|xo-chip>] [--seed <n>] \
+                     [--break <addr>]... [--run]";
+
+const EXIT_USAGE: i32 = 2;
+
+/// Command line options, everything apart from the ROM path is optional.
+struct Options {
+    rom_path: String,
+    config: Config,
+    breakpoints: Vec<u16>,
+    run: bool,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut rom_path = None;
+    let mut options = Options {
+        rom_path: String::new(),
+        config: Config::default(),
+        breakpoints: Vec::new(),
+        run: false,
+    };
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--quirks" => {
+                let name = args.next().ok_or("--quirks needs a preset")?;
+                options.config.quirks = Quirks::from_name(name).ok_or(format!(
+                    "Unknown quirks preset: {} (presets: vip, chip48, schip1.1, schip, xo-chip, default)",
+                    name
+                ))?;
+            }
+            "--variant" => {
+                let name = args.next().ok_or("--variant needs a name")?;
+                options.config.variant =
+                    Variant::from_name(name).ok_or(format!("Unknown variant: {}", name))?;
+            }
+            "--seed" => {
+                let seed = args.next().ok_or("--seed needs a number")?;
+                options.config.seed =
+                    Some(parse_number(seed).ok_or(format!("Invalid seed: {}", seed))?);
+            }
+            "--break" => {
+                let address = args.next().ok_or("--break needs an address")?;
+                // addresses are hex, as everywhere in the debugger
+                let hex = address.strip_prefix("0x").unwrap_or(address);
+                options.breakpoints.push(
+                    u16::from_str_radix(hex, 16)
+                        .map_err(|_| format!("Invalid address: {}", address))?,
+                );
+            }
+            "--run" => options.run = true,
+            _ if rom_path.is_none() && !arg.starts_with("--") => rom_path = Some(arg.clone()),
+            _ => return Err(format!("Unexpected argument: {}", arg)),
+        }
+    }
+    options.rom_path = rom_path.ok_or("No ROM file given")?;
+    Ok(options)
+}
+
+fn exit_with(message: String) -> ! {
+    eprintln!("{}", message);
+    process::exit(EXIT_USAGE);
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let options = parse_args(&args)
+        .unwrap_or_else(|message| exit_with(format!("{}\nUsage: {} {}", message, args[0], USAGE)));
+
+    let rom = fs::read(&options.rom_path).unwrap_or_else(|error| {
+        exit_with(format!("Unable to read {}: {}", options.rom_path, error))
+    });
+    let mut emulator = Emulator::with_config(options.config);
+    if let Err(error) = emulator.load(&rom) {
+        exit_with(format!("Unable to load {}: {}", options.rom_path, error));
+    }
+
+    let mut tui = Tui::new(emulator);
+    for &address in &options.breakpoints {
+        tui.debugger_mut().add_breakpoint(address);
+    }
+    if options.run {
+        tui.start();
+    }
+    if let Err(error) = tui.run() {
+        exit_with(format!("Terminal error: {}", error));
+    }
+}
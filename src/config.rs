@@ -0,0 +1,135 @@
+use crate::{Quirks, RAM_SIZE, XO_CHIP_RAM_SIZE};
+
+/// How the emulator treats RAM addresses that fall outside of the address space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MemoryPolicy {
+    /// stop with `EmuError::MemoryOutOfBounds`
+    #[default]
+    Fault,
+    /// wrap the address around modulo the RAM size, like many real interpreters
+    Wrap,
+}
+
+/// Which instruction set extensions the emulator decodes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Variant {
+    /// the original 35 instructions
+    #[default]
+    Chip8,
+    /// SUPER-CHIP 1.1: high resolution, scrolling, big sprites and fonts
+    SuperChip,
+    /// XO-CHIP: SUPER-CHIP plus 64K of RAM, bitplanes and audio patterns
+    XoChip,
+}
+
+impl Variant {
+    /// Looks up a variant by the name used on the command line.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "chip8" | "chip-8" => Some(Variant::Chip8),
+            "schip" | "superchip" => Some(Variant::SuperChip),
+            "xo-chip" | "xochip" => Some(Variant::XoChip),
+            _ => None,
+        }
+    }
+
+    /// Bytes of RAM the variant has, programs get everything from 0x200 up.
+    pub fn ram_size(self) -> usize {
+        match self {
+            Variant::XoChip => XO_CHIP_RAM_SIZE,
+            _ => RAM_SIZE,
+        }
+    }
+}
+
+/// Parses a number given on the command line, like a seed: decimal, or hex with a 0x prefix.
+pub fn parse_number(text: &str) -> Option<u64> {
+    match text.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+/// Settings the emulator is created with, see `Emulator::with_config`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Config {
+    pub variant: Variant,
+    pub memory_policy: MemoryPolicy,
+    pub stack: StackConfig,
+    pub quirks: Quirks,
+    /// seed for the CXNN random number generator, `None` picks a random one
+    pub seed: Option<u64>,
+    /// instructions `Emulator::run_frame` executes per 60Hz frame
+    pub ticks_per_frame: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            variant: Variant::default(),
+            memory_policy: MemoryPolicy::default(),
+            stack: StackConfig::default(),
+            quirks: Quirks::default(),
+            seed: None,
+            ticks_per_frame: 10,
+        }
+    }
+}
+
+/// What happens when a call is made with a full stack, or a return with an empty one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StackOverflowBehavior {
+    /// stop with `EmuError::StackOverflow` / `EmuError::StackUnderflow`
+    #[default]
+    Fault,
+    /// the stack pointer wraps around and overwrites the oldest entry, a depth of 0 faults
+    /// like `Fault`
+    Wrap,
+    /// calls jump without saving a return address and returns do nothing
+    Ignore,
+}
+
+/// Layout of the subroutine call stack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StackConfig {
+    /// maximum number of nested calls, `None` for unlimited
+    pub depth: Option<usize>,
+    pub overflow: StackOverflowBehavior,
+    /// keep the stack in emulated RAM below 0xED0 like the COSMAC VIP interpreter
+    pub in_ram: bool,
+}
+
+impl StackConfig {
+    /// 12 levels stored in RAM, as on the original COSMAC VIP
+    pub const fn cosmac_vip() -> Self {
+        Self {
+            depth: Some(12),
+            overflow: StackOverflowBehavior::Fault,
+            in_ram: true,
+        }
+    }
+
+    /// 16 levels, as on SUPER-CHIP
+    pub const fn superchip() -> Self {
+        Self {
+            depth: Some(16),
+            overflow: StackOverflowBehavior::Fault,
+            in_ram: false,
+        }
+    }
+
+    /// no limit on nesting, handy when debugging runaway recursion
+    pub const fn unlimited() -> Self {
+        Self {
+            depth: None,
+            overflow: StackOverflowBehavior::Fault,
+            in_ram: false,
+        }
+    }
+}
+
+impl Default for StackConfig {
+    fn default() -> Self {
+        Self::superchip()
+    }
+}
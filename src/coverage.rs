@@ -0,0 +1,190 @@
+//! Code coverage: which bytes of the ROM ran as instructions, which were read as data (by DXYN,
+//! FX65 and so on), which were written and which were never touched.
+//!
+//! Hand the emulator a `Coverage` with `Emulator::set_coverage` and every instruction `tick`
+//! runs marks the bytes it came from and the RAM it accessed. `Coverage::report` then has a
+//! summary, a map of the ROM with a character per byte, and a disassembly listing annotated with
+//! what happened to each line.
+
+use std::fmt::Write;
+
+use crate::disasm::{Disassembly, Syntax};
+use crate::{AccessKind, MemoryAccess, Variant};
+
+const START_ADDR: usize = 0x200;
+// bytes per line of the coverage map
+const MAP_WIDTH: usize = 64;
+
+const EXECUTED: u8 = 1;
+const READ: u8 = 1 << 1;
+const WRITTEN: u8 = 1 << 2;
+// set on the first byte of each instruction run, the rest of it only gets EXECUTED
+const INSTRUCTION: u8 = 1 << 3;
+
+/// How one byte of the ROM was used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ByteCoverage {
+    pub executed: bool,
+    pub read: bool,
+    pub written: bool,
+}
+
+impl ByteCoverage {
+    /// The byte's character in the coverage map.
+    fn symbol(self) -> char {
+        match (self.executed, self.read, self.written) {
+            (true, true, _) => 'B',
+            (true, false, _) => 'X',
+            (false, true, _) => 'R',
+            (false, false, true) => 'W',
+            (false, false, false) => '.',
+        }
+    }
+}
+
+pub struct Coverage {
+    rom: Vec<u8>,
+    variant: Variant,
+    // flags for each byte of the ROM
+    flags: Vec<u8>,
+}
+
+impl Coverage {
+    /// Tracks the bytes of `rom`, which should be the ROM the emulator was loaded with. `variant`
+    /// is used to disassemble it for the listing.
+    pub fn new(rom: &[u8], variant: Variant) -> Self {
+        Self {
+            rom: rom.to_vec(),
+            variant,
+            flags: vec![0; rom.len()],
+        }
+    }
+
+    fn mark(&mut self, address: usize, flag: u8) {
+        if let Some(flags) = address
+            .checked_sub(START_ADDR)
+            .and_then(|index| self.flags.get_mut(index))
+        {
+            *flags |= flag;
+        }
+    }
+
+    /// Marks an instruction `tick` ran, `size` bytes long, and the RAM it accessed.
+    pub(crate) fn record(&mut self, address: u16, size: usize, accesses: &[MemoryAccess]) {
+        let start = address as usize;
+        self.mark(start, INSTRUCTION);
+        for byte in start..start + size {
+            self.mark(byte, EXECUTED);
+        }
+        for access in accesses {
+            // the second half of a long load reads its operand, that's still the instruction
+            if (start..start + size).contains(&access.address) {
+                continue;
+            }
+            let flag = match access.kind {
+                AccessKind::Read => READ,
+                AccessKind::Write => WRITTEN,
+            };
+            self.mark(access.address, flag);
+        }
+    }
+
+    /// How the byte at `address` was used, nothing for addresses outside the ROM.
+    pub fn byte(&self, address: u16) -> ByteCoverage {
+        let flags = (address as usize)
+            .checked_sub(START_ADDR)
+            .and_then(|index| self.flags.get(index))
+            .copied()
+            .unwrap_or(0);
+        ByteCoverage {
+            executed: flags & EXECUTED != 0,
+            read: flags & READ != 0,
+            written: flags & WRITTEN != 0,
+        }
+    }
+
+    fn bytes(&self) -> impl Iterator<Item = ByteCoverage> + '_ {
+        (START_ADDR..START_ADDR + self.flags.len()).map(|address| self.byte(address as u16))
+    }
+
+    /// Summary lines with the number of bytes in each category.
+    pub fn summary(&self) -> String {
+        let total = self.flags.len();
+        let count = |matches: fn(&ByteCoverage) -> bool| self.bytes().filter(matches).count();
+        let categories = [
+            ("executed", count(|byte| byte.executed)),
+            ("read as data", count(|byte| byte.read)),
+            (
+                "executed and read",
+                count(|byte| byte.executed && byte.read),
+            ),
+            ("written", count(|byte| byte.written)),
+            (
+                "untouched",
+                count(|byte| !byte.executed && !byte.read && !byte.written),
+            ),
+        ];
+        let mut summary = format!("Coverage of {} ROM bytes\n", total);
+        for (name, count) in categories {
+            let percent = count as f64 * 100.0 / total.max(1) as f64;
+            // writing to a String can't fail
+            let _ = writeln!(summary, "  {:<18} {:>6}  {:>6.2}%", name, count, percent);
+        }
+        summary
+    }
+
+    /// A character for every ROM byte, `MAP_WIDTH` to a line: X executed, R read as data, B both,
+    /// W only written and . untouched.
+    pub fn map(&self) -> String {
+        let symbols: Vec<char> = self.bytes().map(ByteCoverage::symbol).collect();
+        let mut map = String::new();
+        for (line, chunk) in symbols.chunks(MAP_WIDTH).enumerate() {
+            let _ = writeln!(
+                map,
+                "{:04X}  {}",
+                START_ADDR + line * MAP_WIDTH,
+                chunk.iter().collect::<String>()
+            );
+        }
+        map
+    }
+
+    /// The disassembly with an `XRW` column on every line, each letter there if any byte of the
+    /// line was executed, read or written.
+    pub fn listing(&self) -> String {
+        // anything that ran is code, even if the disassembler couldn't find its way there
+        let entry_points: Vec<u16> = (0..self.flags.len())
+            .filter(|&index| self.flags[index] & INSTRUCTION != 0)
+            .map(|index| (START_ADDR + index) as u16)
+            .collect();
+        let disassembly = Disassembly::with_entry_points(&self.rom, self.variant, &entry_points);
+        disassembly.render_annotated(Syntax::Classic, true, |address, size| {
+            let bytes: Vec<ByteCoverage> = (address..address + size as u16)
+                .map(|address| self.byte(address))
+                .collect();
+            let any = |used: fn(&ByteCoverage) -> bool, symbol| {
+                if bytes.iter().any(used) {
+                    symbol
+                } else {
+                    '-'
+                }
+            };
+            format!(
+                "{}{}{}  ",
+                any(|byte| byte.executed, 'X'),
+                any(|byte| byte.read, 'R'),
+                any(|byte| byte.written, 'W')
+            )
+        })
+    }
+
+    /// The summary, map and listing together.
+    pub fn report(&self) -> String {
+        format!(
+            "{}\nMap (X executed, R read as data, B both, W written, . untouched)\n{}\nListing\n{}",
+            self.summary(),
+            self.map(),
+            self.listing()
+        )
+    }
+}
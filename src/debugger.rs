@@ -0,0 +1,735 @@
+//! A debugger around `Emulator::tick`: breakpoints, watchpoints and stepping.
+//!
+//! The debugger doesn't own the emulator, it's handed one on every call, so any frontend can put
+//! it between its main loop and the emulator. While debugging, run the emulator only through
+//! `Debugger::run` so the debugger can count frames and tick the timers at the right time.
+//!
+//! Start something with `resume`, then call `run` with a budget of instructions until it returns
+//! the reason execution stopped:
+//!
+//! ```text
+//! debugger.resume(&emulator, Command::StepOver);
+//! while debugger.run(&mut emulator, 1000).is_none() {
+//!     // check for the user pressing pause, redraw, ...
+//! }
+//! ```
+//!
+//! A frontend that wants to keep running at 60Hz can resume with `Command::RunToFrame` for the
+//! next frame every frame, and stop its loop on any other reason.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use crate::{AccessKind, EmuError, Emulator, MemoryAccess, Registers};
+
+/// A register that can be watched or used in a condition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Register {
+    V(u8),
+    I,
+    Pc,
+    /// number of return addresses on the stack
+    Sp,
+    DelayTimer,
+    SoundTimer,
+}
+
+impl Register {
+    /// Parses the names used in conditions: `V0`-`VF`, `I`, `PC`, `SP`, `DT` and `ST`.
+    pub fn from_name(name: &str) -> Option<Register> {
+        let name = name.to_ascii_uppercase();
+        match name.as_str() {
+            "I" => Some(Register::I),
+            "PC" => Some(Register::Pc),
+            "SP" => Some(Register::Sp),
+            "DT" => Some(Register::DelayTimer),
+            "ST" => Some(Register::SoundTimer),
+            _ => {
+                let index = name.strip_prefix('V')?;
+                if index.len() != 1 {
+                    return None;
+                }
+                u8::from_str_radix(index, 16).ok().map(Register::V)
+            }
+        }
+    }
+
+    pub fn read(self, registers: &Registers) -> u16 {
+        match self {
+            Register::V(index) => registers.v[index as usize & 0xf] as u16,
+            Register::I => registers.i,
+            Register::Pc => registers.program_counter,
+            Register::Sp => registers.stack_pointer as u16,
+            Register::DelayTimer => registers.delay_timer as u16,
+            Register::SoundTimer => registers.sound_timer as u16,
+        }
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Register::V(index) => write!(f, "V{:X}", index),
+            Register::I => write!(f, "I"),
+            Register::Pc => write!(f, "PC"),
+            Register::Sp => write!(f, "SP"),
+            Register::DelayTimer => write!(f, "DT"),
+            Register::SoundTimer => write!(f, "ST"),
+        }
+    }
+}
+
+/// The kind of RAM access a watchpoint stops on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    /// reads and writes
+    Access,
+}
+
+impl WatchKind {
+    fn matches(self, kind: AccessKind) -> bool {
+        match self {
+            WatchKind::Read => kind == AccessKind::Read,
+            WatchKind::Write => kind == AccessKind::Write,
+            WatchKind::Access => true,
+        }
+    }
+}
+
+/// What `Debugger::resume` should do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// run until a breakpoint, watchpoint or fault
+    Continue,
+    /// run one instruction
+    StepInto,
+    /// run one instruction, or a whole subroutine if it's a 2NNN call
+    StepOver,
+    /// run until the current subroutine returns, at the top level this is `Continue`
+    StepOut,
+    /// run until the given frame starts, see `Debugger::frame`
+    RunToFrame(u64),
+}
+
+/// Why `Debugger::run` stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// the step asked for with `resume` finished
+    Step,
+    /// the program counter reached a breakpoint, before running the instruction there
+    Breakpoint { address: u16 },
+    /// the instruction at `address` accessed a watched byte of RAM
+    Watchpoint { address: u16, access: MemoryAccess },
+    /// a watched register changed
+    RegisterChanged {
+        register: Register,
+        old: u16,
+        new: u16,
+    },
+    /// `Command::RunToFrame` reached its frame
+    Frame { frame: u64 },
+    /// the emulator faulted, the program counter points at the faulting instruction
+    Fault(EmuError),
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopReason::Step => write!(f, "step finished"),
+            StopReason::Breakpoint { address } => write!(f, "breakpoint at {:03X}", address),
+            StopReason::Watchpoint { address, access } => {
+                let verb = match access.kind {
+                    AccessKind::Read => "read",
+                    AccessKind::Write => "wrote",
+                };
+                write!(
+                    f,
+                    "instruction at {:03X} {} {:02X} at {:03X}",
+                    address, verb, access.value, access.address
+                )
+            }
+            StopReason::RegisterChanged { register, old, new } => {
+                write!(f, "{} changed from {:X} to {:X}", register, old, new)
+            }
+            StopReason::Frame { frame } => write!(f, "reached frame {}", frame),
+            StopReason::Fault(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+/// A condition couldn't be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConditionError {
+    /// byte offset into the condition where the problem is
+    pub position: usize,
+    pub message: &'static str,
+}
+
+impl fmt::Display for ConditionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at column {}", self.message, self.position + 1)
+    }
+}
+
+impl std::error::Error for ConditionError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CompareOp {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    Number(u32),
+    Register(Register),
+    /// the byte of RAM at an address
+    Memory(Box<Expr>),
+    Not(Box<Expr>),
+    Compare(Box<Expr>, CompareOp, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn evaluate(&self, emulator: &Emulator, registers: &Registers) -> u32 {
+        match self {
+            Expr::Number(value) => *value,
+            Expr::Register(register) => register.read(registers) as u32,
+            Expr::Memory(address) => {
+                let address = address.evaluate(emulator, registers) as usize;
+                emulator.ram().get(address).copied().unwrap_or(0) as u32
+            }
+            Expr::Not(value) => (value.evaluate(emulator, registers) == 0) as u32,
+            Expr::Compare(left, op, right) => {
+                let left = left.evaluate(emulator, registers);
+                let right = right.evaluate(emulator, registers);
+                let result = match op {
+                    CompareOp::Equal => left == right,
+                    CompareOp::NotEqual => left != right,
+                    CompareOp::Less => left < right,
+                    CompareOp::LessOrEqual => left <= right,
+                    CompareOp::Greater => left > right,
+                    CompareOp::GreaterOrEqual => left >= right,
+                };
+                result as u32
+            }
+            Expr::And(left, right) => {
+                (left.evaluate(emulator, registers) != 0
+                    && right.evaluate(emulator, registers) != 0) as u32
+            }
+            Expr::Or(left, right) => {
+                (left.evaluate(emulator, registers) != 0
+                    || right.evaluate(emulator, registers) != 0) as u32
+            }
+        }
+    }
+}
+
+/// A condition for a breakpoint, like `V3 == 0x10 && I > 0x300`.
+///
+/// Conditions compare registers (`V0`-`VF`, `I`, `PC`, `SP`, `DT`, `ST`), bytes of RAM
+/// (`[0x300]`, `[I]`) and numbers (decimal or `0x` hex) with
+/// `==`, `!=`, `<`, `<=`, `>` and `>=`, combined with `&&`, `||`, `!` and parentheses.
+/// A value on its own is true when it isn't zero.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Condition {
+    source: String,
+    expr: Expr,
+}
+
+impl Condition {
+    pub fn parse(source: &str) -> Result<Condition, ConditionError> {
+        let mut parser = Parser {
+            source: source.as_bytes(),
+            position: 0,
+        };
+        let expr = parser.or()?;
+        parser.skip_whitespace();
+        if parser.position < parser.source.len() {
+            return Err(parser.error("unexpected input"));
+        }
+        Ok(Condition {
+            source: source.trim().to_string(),
+            expr,
+        })
+    }
+
+    pub fn evaluate(&self, emulator: &Emulator) -> bool {
+        self.expr.evaluate(emulator, &emulator.registers()) != 0
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+/// Recursive descent parser for conditions, lowest precedence first.
+struct Parser<'a> {
+    source: &'a [u8],
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &'static str) -> ConditionError {
+        ConditionError {
+            position: self.position,
+            message,
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self
+            .source
+            .get(self.position)
+            .is_some_and(|byte| byte.is_ascii_whitespace())
+        {
+            self.position += 1;
+        }
+    }
+
+    /// Consumes `token` if it's next.
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        if self.source[self.position..].starts_with(token.as_bytes()) {
+            self.position += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, ConditionError> {
+        let mut expr = self.and()?;
+        while self.eat("||") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, ConditionError> {
+        let mut expr = self.comparison()?;
+        while self.eat("&&") {
+            expr = Expr::And(Box::new(expr), Box::new(self.comparison()?));
+        }
+        Ok(expr)
+    }
+
+    fn comparison(&mut self) -> Result<Expr, ConditionError> {
+        let left = self.operand()?;
+        // two character operators first so `<=` isn't read as `<`
+        let op = [
+            ("==", CompareOp::Equal),
+            ("!=", CompareOp::NotEqual),
+            ("<=", CompareOp::LessOrEqual),
+            (">=", CompareOp::GreaterOrEqual),
+            ("<", CompareOp::Less),
+            (">", CompareOp::Greater),
+        ]
+        .into_iter()
+        .find(|(token, _)| self.eat(token));
+        match op {
+            Some((_, op)) => Ok(Expr::Compare(Box::new(left), op, Box::new(self.operand()?))),
+            None => Ok(left),
+        }
+    }
+
+    fn operand(&mut self) -> Result<Expr, ConditionError> {
+        if self.eat("(") {
+            let expr = self.or()?;
+            if !self.eat(")") {
+                return Err(self.error("expected )"));
+            }
+            return Ok(expr);
+        }
+        if self.eat("[") {
+            let address = self.or()?;
+            if !self.eat("]") {
+                return Err(self.error("expected ]"));
+            }
+            return Ok(Expr::Memory(Box::new(address)));
+        }
+        // `!` but not the start of `!=`
+        if !self.source[self.position..].starts_with(b"!=") && self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.operand()?)));
+        }
+        self.skip_whitespace();
+        let start = self.position;
+        while self
+            .source
+            .get(self.position)
+            .is_some_and(|byte| byte.is_ascii_alphanumeric())
+        {
+            self.position += 1;
+        }
+        let word = std::str::from_utf8(&self.source[start..self.position])
+            .expect("only ASCII was consumed");
+        if word.is_empty() {
+            return Err(self.error("expected a register or number"));
+        }
+        if word.starts_with(|c: char| c.is_ascii_digit()) {
+            let value = match word.strip_prefix("0x").or(word.strip_prefix("0X")) {
+                Some(hex) => u32::from_str_radix(hex, 16),
+                None => word.parse(),
+            };
+            value.map(Expr::Number).map_err(|_| ConditionError {
+                position: start,
+                message: "invalid number",
+            })
+        } else {
+            Register::from_name(word)
+                .map(Expr::Register)
+                .ok_or(ConditionError {
+                    position: start,
+                    message: "unknown register",
+                })
+        }
+    }
+}
+
+/// Where the current command stops, worked out when it's resumed.
+#[derive(Debug, Clone, Copy)]
+enum Goal {
+    Continue,
+    StepInto,
+    /// the call returned to `return_address` without going deeper than `depth`
+    StepOver {
+        return_address: u16,
+        depth: usize,
+    },
+    /// the stack got shallower than `depth`
+    StepOut {
+        depth: usize,
+    },
+    Frame(u64),
+}
+
+#[derive(Default)]
+pub struct Debugger {
+    breakpoints: BTreeMap<u16, Option<Condition>>,
+    watchpoints: BTreeMap<usize, WatchKind>,
+    watched_registers: BTreeSet<Register>,
+    goal: Option<Goal>,
+    frame: u64,
+    ticks_in_frame: usize,
+    // where the emulator was when resumed, a breakpoint there shouldn't stop it straight away
+    resume_address: Option<u16>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.breakpoints.insert(address, None);
+    }
+
+    /// A breakpoint that only stops when `condition` is true.
+    pub fn add_conditional_breakpoint(&mut self, address: u16, condition: Condition) {
+        self.breakpoints.insert(address, Some(condition));
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.remove(&address).is_some()
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = (u16, Option<&Condition>)> {
+        self.breakpoints
+            .iter()
+            .map(|(&address, condition)| (address, condition.as_ref()))
+    }
+
+    /// Stops after any instruction that accesses the byte at `address` the way `kind` says.
+    pub fn add_watchpoint(&mut self, address: usize, kind: WatchKind) {
+        self.watchpoints.insert(address, kind);
+    }
+
+    pub fn remove_watchpoint(&mut self, address: usize) -> bool {
+        self.watchpoints.remove(&address).is_some()
+    }
+
+    pub fn watchpoints(&self) -> impl Iterator<Item = (usize, WatchKind)> + '_ {
+        self.watchpoints
+            .iter()
+            .map(|(&address, &kind)| (address, kind))
+    }
+
+    /// Stops after any instruction, or timer tick, that changes `register`.
+    pub fn watch_register(&mut self, register: Register) {
+        self.watched_registers.insert(register);
+    }
+
+    pub fn unwatch_register(&mut self, register: Register) -> bool {
+        self.watched_registers.remove(&register)
+    }
+
+    pub fn watched_registers(&self) -> impl Iterator<Item = Register> + '_ {
+        self.watched_registers.iter().copied()
+    }
+
+    /// Number of whole frames run through the debugger.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// True between `resume` and `run` returning a reason.
+    pub fn is_running(&self) -> bool {
+        self.goal.is_some()
+    }
+
+    /// Drops the current command, `run` does nothing until the next `resume`.
+    pub fn pause(&mut self) {
+        self.goal = None;
+    }
+
+    /// Starts a command, which `run` then carries out.
+    pub fn resume(&mut self, emulator: &Emulator, command: Command) {
+        let registers = emulator.registers();
+        let pc = registers.program_counter;
+        self.goal = Some(match command {
+            Command::Continue => Goal::Continue,
+            Command::StepInto => Goal::StepInto,
+            Command::StepOver => {
+                let ram = emulator.ram();
+                let opcode = ram.get(pc as usize).copied().unwrap_or(0);
+                if opcode & 0xf0 == 0x20 {
+                    Goal::StepOver {
+                        return_address: pc.wrapping_add(2),
+                        depth: registers.stack_pointer,
+                    }
+                } else {
+                    Goal::StepInto
+                }
+            }
+            Command::StepOut => Goal::StepOut {
+                depth: registers.stack_pointer,
+            },
+            Command::RunToFrame(frame) => Goal::Frame(frame),
+        });
+        self.resume_address = Some(pc);
+    }
+
+    /// Runs at most `max_ticks` instructions of the resumed command.
+    /// Returns why it stopped, or `None` if it's still going once the budget is used up.
+    pub fn run(&mut self, emulator: &mut Emulator, max_ticks: usize) -> Option<StopReason> {
+        for _ in 0..max_ticks {
+            let goal = self.goal?;
+            let before = emulator.registers();
+            let pc = before.program_counter;
+
+            match goal {
+                Goal::StepOver {
+                    return_address,
+                    depth,
+                } if pc == return_address && before.stack_pointer <= depth => {
+                    return self.stop(StopReason::Step);
+                }
+                Goal::Frame(frame) if self.frame >= frame => {
+                    return self.stop(StopReason::Frame { frame: self.frame });
+                }
+                _ => {}
+            }
+            if self.resume_address.take() != Some(pc) {
+                if let Some(condition) = self.breakpoints.get(&pc) {
+                    if condition
+                        .as_ref()
+                        .is_none_or(|condition| condition.evaluate(emulator))
+                    {
+                        return self.stop(StopReason::Breakpoint { address: pc });
+                    }
+                }
+            }
+
+            if let Err(error) = emulator.tick() {
+                return self.stop(StopReason::Fault(error));
+            }
+            self.ticks_in_frame += 1;
+            if self.ticks_in_frame >= emulator.config().ticks_per_frame {
+                self.ticks_in_frame = 0;
+                emulator.tick_timers();
+                self.frame += 1;
+            }
+
+            let watched = emulator.last_accesses().iter().find(|access| {
+                self.watchpoints
+                    .get(&access.address)
+                    .is_some_and(|kind| kind.matches(access.kind))
+            });
+            if let Some(&access) = watched {
+                return self.stop(StopReason::Watchpoint {
+                    address: pc,
+                    access,
+                });
+            }
+            let after = emulator.registers();
+            let changed = self
+                .watched_registers
+                .iter()
+                .find(|register| register.read(&before) != register.read(&after));
+            if let Some(&register) = changed {
+                return self.stop(StopReason::RegisterChanged {
+                    register,
+                    old: register.read(&before),
+                    new: register.read(&after),
+                });
+            }
+            match goal {
+                Goal::StepInto => return self.stop(StopReason::Step),
+                Goal::StepOut { depth } if after.stack_pointer < depth => {
+                    return self.stop(StopReason::Step);
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+
+    fn stop(&mut self, reason: StopReason) -> Option<StopReason> {
+        self.goal = None;
+        Some(reason)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn register(register: Register) -> Box<Expr> {
+        Box::new(Expr::Register(register))
+    }
+
+    fn number(value: u32) -> Box<Expr> {
+        Box::new(Expr::Number(value))
+    }
+
+    fn emulator_with(rom: &[u8]) -> Emulator {
+        let mut emulator = Emulator::new();
+        emulator.load(rom).unwrap();
+        emulator
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let condition = Condition::parse("V0 == 1 || V1 != 0x10 && !DT").unwrap();
+        let expected = Expr::Or(
+            Box::new(Expr::Compare(
+                register(Register::V(0)),
+                CompareOp::Equal,
+                number(1),
+            )),
+            Box::new(Expr::And(
+                Box::new(Expr::Compare(
+                    register(Register::V(1)),
+                    CompareOp::NotEqual,
+                    number(0x10),
+                )),
+                Box::new(Expr::Not(register(Register::DelayTimer))),
+            )),
+        );
+        assert_eq!(condition.expr, expected);
+    }
+
+    #[test]
+    fn parses_numbers_and_memory() {
+        let condition = Condition::parse("[i] >= 0x1F && (pc <= 512)").unwrap();
+        let expected = Expr::And(
+            Box::new(Expr::Compare(
+                Box::new(Expr::Memory(register(Register::I))),
+                CompareOp::GreaterOrEqual,
+                number(0x1f),
+            )),
+            Box::new(Expr::Compare(
+                register(Register::Pc),
+                CompareOp::LessOrEqual,
+                number(512),
+            )),
+        );
+        assert_eq!(condition.expr, expected);
+        assert_eq!(condition.to_string(), "[i] >= 0x1F && (pc <= 512)");
+    }
+
+    #[test]
+    fn rejects_bad_conditions() {
+        let error = |source| Condition::parse(source).unwrap_err();
+        assert_eq!(
+            error("VG == 1"),
+            ConditionError {
+                position: 0,
+                message: "unknown register"
+            }
+        );
+        assert_eq!(error("V0 == V10").message, "unknown register");
+        assert_eq!(
+            error("I == 0x3G0"),
+            ConditionError {
+                position: 5,
+                message: "invalid number"
+            }
+        );
+        assert_eq!(error("(V0 == 1").message, "expected )");
+        assert_eq!(error("[I").message, "expected ]");
+        assert_eq!(error("V0 ==").message, "expected a register or number");
+        assert_eq!(error("V0 V1").message, "unexpected input");
+    }
+
+    #[test]
+    fn evaluates_against_the_emulator() {
+        let mut emulator = emulator_with(&[0x12, 0x00]);
+        let condition = Condition::parse("V3 == 0x10 && [0x200] == 0x12").unwrap();
+        assert!(!condition.evaluate(&emulator));
+        emulator.set_v(3, 0x10).unwrap();
+        assert!(condition.evaluate(&emulator));
+    }
+
+    #[test]
+    fn watchpoint_fires_on_store() {
+        // I := 0x300, V0 := 5, V1 := 6, save V0-V1, then loop
+        let mut emulator =
+            emulator_with(&[0xA3, 0x00, 0x60, 0x05, 0x61, 0x06, 0xF1, 0x55, 0x12, 0x08]);
+        let mut debugger = Debugger::new();
+        // FX55 only writes, so a read watchpoint on the first byte stays quiet
+        debugger.add_watchpoint(0x300, WatchKind::Read);
+        debugger.add_watchpoint(0x301, WatchKind::Write);
+        debugger.resume(&emulator, Command::Continue);
+        assert_eq!(
+            debugger.run(&mut emulator, 100),
+            Some(StopReason::Watchpoint {
+                address: 0x206,
+                access: MemoryAccess {
+                    address: 0x301,
+                    kind: AccessKind::Write,
+                    value: 6,
+                },
+            })
+        );
+        assert!(!debugger.is_running());
+    }
+
+    #[test]
+    fn step_over_runs_the_whole_call() {
+        // call 0x206, V0 := 1, loop; 0x206: V1 := 2, return
+        let mut emulator =
+            emulator_with(&[0x22, 0x06, 0x60, 0x01, 0x12, 0x04, 0x61, 0x02, 0x00, 0xEE]);
+        let mut debugger = Debugger::new();
+        debugger.resume(&emulator, Command::StepOver);
+        assert_eq!(debugger.run(&mut emulator, 100), Some(StopReason::Step));
+        let registers = emulator.registers();
+        assert_eq!(registers.program_counter, 0x202);
+        assert_eq!(registers.v[1], 2);
+        assert_eq!(registers.stack_pointer, 0);
+
+        // anything else is a single step
+        debugger.resume(&emulator, Command::StepOver);
+        assert_eq!(debugger.run(&mut emulator, 100), Some(StopReason::Step));
+        assert_eq!(emulator.registers().program_counter, 0x204);
+    }
+}
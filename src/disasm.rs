@@ -0,0 +1,647 @@
+//! Disassembler for CHIP-8, SUPER-CHIP and XO-CHIP programs.
+//!
+//! `Op::decode` turns one instruction into an `Op`, which can be printed in the classic
+//! mnemonic syntax (`LD V0, 0x05`) or in Octo syntax (`v0 := 0x05`).
+//!
+//! `Disassembly` works on a whole ROM: it follows the program's control flow from 0x200 to tell
+//! code from data, and names the targets of jumps, calls and `I` loads so the listing reads like
+//! source. Code only reached through a `BNNN` jump table can't be found this way and shows up as
+//! data.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::Variant;
+
+const START_ADDR: u16 = 0x200;
+// data bytes per line of the listing
+const DATA_BYTES_PER_LINE: usize = 8;
+
+/// Which assembly language to print.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Syntax {
+    /// the usual mnemonics, e.g. `LD V0, 0x05` and `DRW V0, V1, 5`
+    #[default]
+    Classic,
+    /// Octo, e.g. `v0 := 0x05` and `sprite v0 v1 5`, which Octo can assemble again
+    Octo,
+}
+
+impl Syntax {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "classic" => Some(Syntax::Classic),
+            "octo" => Some(Syntax::Octo),
+            _ => None,
+        }
+    }
+}
+
+/// One decoded instruction. `x` and `y` are register numbers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    /// 0000
+    Nop,
+    /// 00E0
+    Clear,
+    /// 00EE
+    Return,
+    /// 00CN
+    ScrollDown(u8),
+    /// 00DN
+    ScrollUp(u8),
+    /// 00FB
+    ScrollRight,
+    /// 00FC
+    ScrollLeft,
+    /// 00FD
+    Exit,
+    /// 00FE
+    LowRes,
+    /// 00FF
+    HighRes,
+    /// 1NNN
+    Jump(u16),
+    /// 2NNN
+    Call(u16),
+    /// 3XNN
+    SkipIfEqual(u8, u8),
+    /// 4XNN
+    SkipIfNotEqual(u8, u8),
+    /// 5XY0
+    SkipIfRegistersEqual(u8, u8),
+    /// 5XY2
+    SaveRange(u8, u8),
+    /// 5XY3
+    LoadRange(u8, u8),
+    /// 6XNN
+    Set(u8, u8),
+    /// 7XNN
+    Add(u8, u8),
+    /// 8XY0
+    Copy(u8, u8),
+    /// 8XY1
+    Or(u8, u8),
+    /// 8XY2
+    And(u8, u8),
+    /// 8XY3
+    Xor(u8, u8),
+    /// 8XY4
+    AddRegisters(u8, u8),
+    /// 8XY5
+    Subtract(u8, u8),
+    /// 8XY6
+    ShiftRight(u8, u8),
+    /// 8XY7
+    SubtractReversed(u8, u8),
+    /// 8XYE
+    ShiftLeft(u8, u8),
+    /// 9XY0
+    SkipIfRegistersNotEqual(u8, u8),
+    /// ANNN
+    SetI(u16),
+    /// BNNN
+    JumpOffset(u16),
+    /// CXNN
+    Random(u8, u8),
+    /// DXYN
+    Draw(u8, u8, u8),
+    /// EX9E
+    SkipIfKey(u8),
+    /// EXA1
+    SkipIfNotKey(u8),
+    /// F000 NNNN
+    SetILong(u16),
+    /// FN01
+    Plane(u8),
+    /// F002
+    Audio,
+    /// FX07
+    GetDelay(u8),
+    /// FX0A
+    WaitKey(u8),
+    /// FX15
+    SetDelay(u8),
+    /// FX18
+    SetSound(u8),
+    /// FX1E
+    AddI(u8),
+    /// FX29
+    Font(u8),
+    /// FX30
+    BigFont(u8),
+    /// FX3A
+    Pitch(u8),
+    /// FX33
+    Bcd(u8),
+    /// FX55
+    Save(u8),
+    /// FX65
+    Load(u8),
+    /// FX75
+    SaveFlags(u8),
+    /// FX85
+    LoadFlags(u8),
+}
+
+/// How control can leave an instruction.
+enum Flow {
+    /// carries on with the next instruction
+    Next,
+    /// goes to the address and nowhere else
+    Jump(u16),
+    /// goes to the address and comes back to the next instruction
+    Call(u16),
+    /// runs or skips the next instruction
+    Skip,
+    /// doesn't continue anywhere that can be worked out
+    Stop,
+}
+
+impl Op {
+    /// Decodes the instruction at the start of `bytes`, which needs 4 bytes for XO-CHIP's
+    /// `F000 NNNN`. Returns `None` for opcodes `variant` doesn't have.
+    pub fn decode(bytes: &[u8], variant: Variant) -> Option<Op> {
+        let opcode = u16::from_be_bytes([*bytes.first()?, *bytes.get(1)?]);
+        let superchip = variant != Variant::Chip8;
+        let xo_chip = variant == Variant::XoChip;
+        let x = ((opcode >> 8) & 0xf) as u8;
+        let y = ((opcode >> 4) & 0xf) as u8;
+        let n = (opcode & 0xf) as u8;
+        let nn = opcode as u8;
+        let nnn = opcode & 0xfff;
+        let op = match (opcode >> 12, x, y, n) {
+            (0, 0, 0, 0) => Op::Nop,
+            (0, 0, 0xe, 0) => Op::Clear,
+            (0, 0, 0xc, _) if superchip => Op::ScrollDown(n),
+            (0, 0, 0xd, _) if xo_chip => Op::ScrollUp(n),
+            (0, 0, 0xf, 0xb) if superchip => Op::ScrollRight,
+            (0, 0, 0xf, 0xc) if superchip => Op::ScrollLeft,
+            (0, 0, 0xf, 0xd) if superchip => Op::Exit,
+            (0, 0, 0xf, 0xe) if superchip => Op::LowRes,
+            (0, 0, 0xf, 0xf) if superchip => Op::HighRes,
+            (0, 0, 0xe, 0xe) => Op::Return,
+            (1, _, _, _) => Op::Jump(nnn),
+            (2, _, _, _) => Op::Call(nnn),
+            (3, _, _, _) => Op::SkipIfEqual(x, nn),
+            (4, _, _, _) => Op::SkipIfNotEqual(x, nn),
+            (5, _, _, 0) => Op::SkipIfRegistersEqual(x, y),
+            (5, _, _, 2) if xo_chip => Op::SaveRange(x, y),
+            (5, _, _, 3) if xo_chip => Op::LoadRange(x, y),
+            (6, _, _, _) => Op::Set(x, nn),
+            (7, _, _, _) => Op::Add(x, nn),
+            (8, _, _, 0) => Op::Copy(x, y),
+            (8, _, _, 1) => Op::Or(x, y),
+            (8, _, _, 2) => Op::And(x, y),
+            (8, _, _, 3) => Op::Xor(x, y),
+            (8, _, _, 4) => Op::AddRegisters(x, y),
+            (8, _, _, 5) => Op::Subtract(x, y),
+            (8, _, _, 6) => Op::ShiftRight(x, y),
+            (8, _, _, 7) => Op::SubtractReversed(x, y),
+            (8, _, _, 0xe) => Op::ShiftLeft(x, y),
+            (9, _, _, 0) => Op::SkipIfRegistersNotEqual(x, y),
+            (0xa, _, _, _) => Op::SetI(nnn),
+            (0xb, _, _, _) => Op::JumpOffset(nnn),
+            (0xc, _, _, _) => Op::Random(x, nn),
+            (0xd, _, _, _) => Op::Draw(x, y, n),
+            (0xe, _, 9, 0xe) => Op::SkipIfKey(x),
+            (0xe, _, 0xa, 1) => Op::SkipIfNotKey(x),
+            (0xf, 0, 0, 0) if xo_chip => {
+                Op::SetILong(u16::from_be_bytes([*bytes.get(2)?, *bytes.get(3)?]))
+            }
+            (0xf, _, 0, 1) if xo_chip => Op::Plane(x),
+            (0xf, 0, 0, 2) if xo_chip => Op::Audio,
+            (0xf, _, 0, 7) => Op::GetDelay(x),
+            (0xf, _, 0, 0xa) => Op::WaitKey(x),
+            (0xf, _, 1, 5) => Op::SetDelay(x),
+            (0xf, _, 1, 8) => Op::SetSound(x),
+            (0xf, _, 1, 0xe) => Op::AddI(x),
+            (0xf, _, 2, 9) => Op::Font(x),
+            (0xf, _, 3, 0) if superchip => Op::BigFont(x),
+            (0xf, _, 3, 0xa) if xo_chip => Op::Pitch(x),
+            (0xf, _, 3, 3) => Op::Bcd(x),
+            (0xf, _, 5, 5) => Op::Save(x),
+            (0xf, _, 6, 5) => Op::Load(x),
+            (0xf, _, 7, 5) if superchip => Op::SaveFlags(x),
+            (0xf, _, 8, 5) if superchip => Op::LoadFlags(x),
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    /// Length of the instruction in bytes.
+    pub fn size(self) -> u16 {
+        match self {
+            Op::SetILong(_) => 4,
+            _ => 2,
+        }
+    }
+
+    /// The address the instruction jumps to, calls or points `I` at.
+    pub fn target(self) -> Option<u16> {
+        match self {
+            Op::Jump(address)
+            | Op::Call(address)
+            | Op::SetI(address)
+            | Op::JumpOffset(address)
+            | Op::SetILong(address) => Some(address),
+            _ => None,
+        }
+    }
+
+    fn flow(self) -> Flow {
+        match self {
+            Op::Jump(address) => Flow::Jump(address),
+            Op::Call(address) => Flow::Call(address),
+            Op::Return | Op::Exit | Op::JumpOffset(_) => Flow::Stop,
+            Op::SkipIfEqual(..)
+            | Op::SkipIfNotEqual(..)
+            | Op::SkipIfRegistersEqual(..)
+            | Op::SkipIfRegistersNotEqual(..)
+            | Op::SkipIfKey(_)
+            | Op::SkipIfNotKey(_) => Flow::Skip,
+            _ => Flow::Next,
+        }
+    }
+
+    /// Formats the instruction with plain addresses.
+    pub fn text(self, syntax: Syntax) -> String {
+        self.text_with(syntax, &|address| format!("0x{:03X}", address))
+    }
+
+    /// Formats the instruction, naming the address it refers to with `name`.
+    fn text_with(self, syntax: Syntax, name: &dyn Fn(u16) -> String) -> String {
+        match syntax {
+            Syntax::Classic => self.classic(name),
+            Syntax::Octo => self.octo(name),
+        }
+    }
+
+    fn classic(self, name: &dyn Fn(u16) -> String) -> String {
+        match self {
+            Op::Nop => "NOP".to_string(),
+            Op::Clear => "CLS".to_string(),
+            Op::Return => "RET".to_string(),
+            Op::ScrollDown(n) => format!("SCD {}", n),
+            Op::ScrollUp(n) => format!("SCU {}", n),
+            Op::ScrollRight => "SCR".to_string(),
+            Op::ScrollLeft => "SCL".to_string(),
+            Op::Exit => "EXIT".to_string(),
+            Op::LowRes => "LOW".to_string(),
+            Op::HighRes => "HIGH".to_string(),
+            Op::Jump(address) => format!("JP {}", name(address)),
+            Op::Call(address) => format!("CALL {}", name(address)),
+            Op::SkipIfEqual(x, nn) => format!("SE V{:X}, 0x{:02X}", x, nn),
+            Op::SkipIfNotEqual(x, nn) => format!("SNE V{:X}, 0x{:02X}", x, nn),
+            Op::SkipIfRegistersEqual(x, y) => format!("SE V{:X}, V{:X}", x, y),
+            Op::SaveRange(x, y) => format!("SAVE V{:X} - V{:X}", x, y),
+            Op::LoadRange(x, y) => format!("LOAD V{:X} - V{:X}", x, y),
+            Op::Set(x, nn) => format!("LD V{:X}, 0x{:02X}", x, nn),
+            Op::Add(x, nn) => format!("ADD V{:X}, 0x{:02X}", x, nn),
+            Op::Copy(x, y) => format!("LD V{:X}, V{:X}", x, y),
+            Op::Or(x, y) => format!("OR V{:X}, V{:X}", x, y),
+            Op::And(x, y) => format!("AND V{:X}, V{:X}", x, y),
+            Op::Xor(x, y) => format!("XOR V{:X}, V{:X}", x, y),
+            Op::AddRegisters(x, y) => format!("ADD V{:X}, V{:X}", x, y),
+            Op::Subtract(x, y) => format!("SUB V{:X}, V{:X}", x, y),
+            Op::ShiftRight(x, y) => format!("SHR V{:X}, V{:X}", x, y),
+            Op::SubtractReversed(x, y) => format!("SUBN V{:X}, V{:X}", x, y),
+            Op::ShiftLeft(x, y) => format!("SHL V{:X}, V{:X}", x, y),
+            Op::SkipIfRegistersNotEqual(x, y) => format!("SNE V{:X}, V{:X}", x, y),
+            Op::SetI(address) => format!("LD I, {}", name(address)),
+            Op::JumpOffset(address) => format!("JP V0, {}", name(address)),
+            Op::Random(x, nn) => format!("RND V{:X}, 0x{:02X}", x, nn),
+            Op::Draw(x, y, n) => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+            Op::SkipIfKey(x) => format!("SKP V{:X}", x),
+            Op::SkipIfNotKey(x) => format!("SKNP V{:X}", x),
+            Op::SetILong(address) => format!("LD I, LONG {}", name(address)),
+            Op::Plane(n) => format!("PLANE {}", n),
+            Op::Audio => "AUDIO".to_string(),
+            Op::GetDelay(x) => format!("LD V{:X}, DT", x),
+            Op::WaitKey(x) => format!("LD V{:X}, K", x),
+            Op::SetDelay(x) => format!("LD DT, V{:X}", x),
+            Op::SetSound(x) => format!("LD ST, V{:X}", x),
+            Op::AddI(x) => format!("ADD I, V{:X}", x),
+            Op::Font(x) => format!("LD F, V{:X}", x),
+            Op::BigFont(x) => format!("LD HF, V{:X}", x),
+            Op::Pitch(x) => format!("PITCH V{:X}", x),
+            Op::Bcd(x) => format!("LD B, V{:X}", x),
+            Op::Save(x) => format!("LD [I], V{:X}", x),
+            Op::Load(x) => format!("LD V{:X}, [I]", x),
+            Op::SaveFlags(x) => format!("LD R, V{:X}", x),
+            Op::LoadFlags(x) => format!("LD V{:X}, R", x),
+        }
+    }
+
+    fn octo(self, name: &dyn Fn(u16) -> String) -> String {
+        // Octo's conditionals say when the next instruction runs, so they read as the
+        // opposite of the skip
+        match self {
+            // Octo has no mnemonic for 0000, so it's written as data
+            Op::Nop => "0x00 0x00".to_string(),
+            Op::Clear => "clear".to_string(),
+            Op::Return => "return".to_string(),
+            Op::ScrollDown(n) => format!("scroll-down {}", n),
+            Op::ScrollUp(n) => format!("scroll-up {}", n),
+            Op::ScrollRight => "scroll-right".to_string(),
+            Op::ScrollLeft => "scroll-left".to_string(),
+            Op::Exit => "exit".to_string(),
+            Op::LowRes => "lores".to_string(),
+            Op::HighRes => "hires".to_string(),
+            Op::Jump(address) => format!("jump {}", name(address)),
+            Op::Call(address) => format!(":call {}", name(address)),
+            Op::SkipIfEqual(x, nn) => format!("if v{:x} != 0x{:02X} then", x, nn),
+            Op::SkipIfNotEqual(x, nn) => format!("if v{:x} == 0x{:02X} then", x, nn),
+            Op::SkipIfRegistersEqual(x, y) => format!("if v{:x} != v{:x} then", x, y),
+            Op::SaveRange(x, y) => format!("save v{:x} - v{:x}", x, y),
+            Op::LoadRange(x, y) => format!("load v{:x} - v{:x}", x, y),
+            Op::Set(x, nn) => format!("v{:x} := 0x{:02X}", x, nn),
+            Op::Add(x, nn) => format!("v{:x} += 0x{:02X}", x, nn),
+            Op::Copy(x, y) => format!("v{:x} := v{:x}", x, y),
+            Op::Or(x, y) => format!("v{:x} |= v{:x}", x, y),
+            Op::And(x, y) => format!("v{:x} &= v{:x}", x, y),
+            Op::Xor(x, y) => format!("v{:x} ^= v{:x}", x, y),
+            Op::AddRegisters(x, y) => format!("v{:x} += v{:x}", x, y),
+            Op::Subtract(x, y) => format!("v{:x} -= v{:x}", x, y),
+            Op::ShiftRight(x, y) => format!("v{:x} >>= v{:x}", x, y),
+            Op::SubtractReversed(x, y) => format!("v{:x} =- v{:x}", x, y),
+            Op::ShiftLeft(x, y) => format!("v{:x} <<= v{:x}", x, y),
+            Op::SkipIfRegistersNotEqual(x, y) => format!("if v{:x} == v{:x} then", x, y),
+            Op::SetI(address) => format!("i := {}", name(address)),
+            Op::JumpOffset(address) => format!("jump0 {}", name(address)),
+            Op::Random(x, nn) => format!("v{:x} := random 0x{:02X}", x, nn),
+            Op::Draw(x, y, n) => format!("sprite v{:x} v{:x} {}", x, y, n),
+            Op::SkipIfKey(x) => format!("if v{:x} -key then", x),
+            Op::SkipIfNotKey(x) => format!("if v{:x} key then", x),
+            Op::SetILong(address) => format!("i := long {}", name(address)),
+            Op::Plane(n) => format!("plane {}", n),
+            Op::Audio => "audio".to_string(),
+            Op::GetDelay(x) => format!("v{:x} := delay", x),
+            Op::WaitKey(x) => format!("v{:x} := key", x),
+            Op::SetDelay(x) => format!("delay := v{:x}", x),
+            Op::SetSound(x) => format!("buzzer := v{:x}", x),
+            Op::AddI(x) => format!("i += v{:x}", x),
+            Op::Font(x) => format!("i := hex v{:x}", x),
+            Op::BigFont(x) => format!("i := bighex v{:x}", x),
+            Op::Pitch(x) => format!("pitch := v{:x}", x),
+            Op::Bcd(x) => format!("bcd v{:x}", x),
+            Op::Save(x) => format!("save v{:x}", x),
+            Op::Load(x) => format!("load v{:x}", x),
+            Op::SaveFlags(x) => format!("saveflags v{:x}", x),
+            Op::LoadFlags(x) => format!("loadflags v{:x}", x),
+        }
+    }
+}
+
+/// A ROM split into code and data, with labels for the addresses the code refers to.
+pub struct Disassembly {
+    rom: Vec<u8>,
+    variant: Variant,
+    /// addresses of the instructions reachable from 0x200
+    code: BTreeSet<u16>,
+    labels: BTreeMap<u16, String>,
+}
+
+impl Disassembly {
+    pub fn new(rom: &[u8], variant: Variant) -> Self {
+        Self::with_entry_points(rom, variant, &[])
+    }
+
+    /// Also traces from `entry_points`, for code the tracing can't find on its own like the
+    /// targets of BNNN jumps or addresses seen running.
+    /// Bytes that don't fit in the variant's RAM are left out, like `Emulator::load` rejects them.
+    pub fn with_entry_points(rom: &[u8], variant: Variant, entry_points: &[u16]) -> Self {
+        let max = variant.ram_size() - START_ADDR as usize;
+        let mut disassembly = Self {
+            rom: rom[..rom.len().min(max)].to_vec(),
+            variant,
+            code: BTreeSet::new(),
+            labels: BTreeMap::new(),
+        };
+        disassembly.trace(entry_points);
+        disassembly.name_targets();
+        disassembly
+    }
+
+    fn end(&self) -> usize {
+        START_ADDR as usize + self.rom.len()
+    }
+
+    fn contains(&self, address: u16) -> bool {
+        (START_ADDR as usize..self.end()).contains(&(address as usize))
+    }
+
+    /// The instruction at `address`, if it's inside the ROM and decodes.
+    pub fn op_at(&self, address: u16) -> Option<Op> {
+        if !self.contains(address) {
+            return None;
+        }
+        Op::decode(&self.rom[(address - START_ADDR) as usize..], self.variant)
+    }
+
+    /// Follows every path through the program from 0x200 and the entry points, marking the
+    /// instructions on them.
+    fn trace(&mut self, entry_points: &[u16]) {
+        let mut pending = vec![START_ADDR];
+        pending.extend(entry_points);
+        while let Some(address) = pending.pop() {
+            if self.code.contains(&address) {
+                continue;
+            }
+            let Some(op) = self.op_at(address) else {
+                continue;
+            };
+            self.code.insert(address);
+            let next = address.wrapping_add(op.size());
+            match op.flow() {
+                Flow::Next => pending.push(next),
+                Flow::Jump(target) => pending.push(target),
+                Flow::Call(target) => pending.extend([target, next]),
+                Flow::Skip => {
+                    // the skipped instruction can be XO-CHIP's 4 byte long load
+                    let skipped = self.op_at(next).map_or(2, Op::size);
+                    pending.extend([next, next.wrapping_add(skipped)]);
+                }
+                Flow::Stop => {}
+            }
+        }
+    }
+
+    /// Labels the targets inside the ROM that start a line of the listing.
+    fn name_targets(&mut self) {
+        let mut labels = BTreeMap::new();
+        for &address in &self.code {
+            let Some(op) = self.op_at(address) else {
+                continue;
+            };
+            let Some(target) = op.target() else {
+                continue;
+            };
+            if !self.contains(target) {
+                continue;
+            }
+            let prefix = match op {
+                Op::Call(_) => "sub",
+                Op::Jump(_) | Op::JumpOffset(_) => "label",
+                _ => "data",
+            };
+            // calls name a label over jumps, which name it over data
+            let name = format!("{}_{:03X}", prefix, target);
+            labels
+                .entry(target)
+                .and_modify(|existing: &mut String| {
+                    if rank(&name) < rank(existing) {
+                        *existing = name.clone();
+                    }
+                })
+                .or_insert(name);
+        }
+        self.labels = labels;
+        // a target in the middle of an instruction, even one that's also reachable at an odd
+        // offset, can't have a label in the listing. Dropping those labels doesn't move any
+        // line, as data lines never run into an instruction.
+        let starts: BTreeSet<u16> = self.lines().map(|(address, ..)| address).collect();
+        self.labels.retain(|address, _| starts.contains(address));
+    }
+
+    /// The lines of the listing as (address, size, instruction), `None` for data.
+    fn lines(&self) -> impl Iterator<Item = (u16, usize, Option<Op>)> + '_ {
+        let mut address = START_ADDR as usize;
+        std::iter::from_fn(move || {
+            if address >= self.end() {
+                return None;
+            }
+            let line = match self.op_at(address as u16) {
+                Some(op) if self.is_code(address as u16) => {
+                    (address as u16, op.size() as usize, Some(op))
+                }
+                _ => (address as u16, self.data_run(address), None),
+            };
+            address += line.1;
+            Some(line)
+        })
+    }
+
+    /// True if the instruction at `address` was reached from 0x200.
+    pub fn is_code(&self, address: u16) -> bool {
+        self.code.contains(&address)
+    }
+
+    /// The labels the listing uses, by address.
+    pub fn labels(&self) -> &BTreeMap<u16, String> {
+        &self.labels
+    }
+
+    /// The listing of the whole ROM. With `columns` every line starts with (or for Octo, ends
+    /// with a comment holding) its address and raw bytes.
+    pub fn render(&self, syntax: Syntax, columns: bool) -> String {
+        self.render_annotated(syntax, columns, |_, _| String::new())
+    }
+
+    /// Like `render`, with every instruction and data line starting with what `annotate` returns
+    /// for its address and size.
+    pub fn render_annotated(
+        &self,
+        syntax: Syntax,
+        columns: bool,
+        annotate: impl Fn(u16, usize) -> String,
+    ) -> String {
+        let name = |address: u16| match self.labels.get(&address) {
+            Some(label) => label.clone(),
+            None => format!("0x{:03X}", address),
+        };
+        let mut listing = String::new();
+        for (address, size, op) in self.lines() {
+            if let Some(label) = self.labels.get(&address) {
+                match syntax {
+                    Syntax::Classic => listing.push_str(&format!("{}:\n", label)),
+                    Syntax::Octo => listing.push_str(&format!(": {}\n", label)),
+                }
+            }
+            let text = match op {
+                Some(op) => op.text_with(syntax, &name),
+                None => self.data_text(address as usize, size, syntax),
+            };
+            let bytes = &self.rom[(address - START_ADDR) as usize..][..size];
+            listing.push_str(&annotate(address, size));
+            listing.push_str(&self.line(address, bytes, &text, syntax, columns));
+        }
+        listing
+    }
+
+    /// Number of data bytes from `address` to put on one line, stopping at code and labels.
+    fn data_run(&self, address: usize) -> usize {
+        let mut size = 1;
+        while size < DATA_BYTES_PER_LINE
+            && address + size < self.end()
+            && !self.is_code((address + size) as u16)
+            && !self.labels.contains_key(&((address + size) as u16))
+        {
+            size += 1;
+        }
+        size
+    }
+
+    fn data_text(&self, address: usize, size: usize, syntax: Syntax) -> String {
+        let bytes = &self.rom[address - START_ADDR as usize..][..size];
+        let bytes: Vec<String> = bytes.iter().map(|byte| format!("0x{:02X}", byte)).collect();
+        match syntax {
+            Syntax::Classic => format!("DB {}", bytes.join(", ")),
+            Syntax::Octo => bytes.join(" "),
+        }
+    }
+
+    fn line(
+        &self,
+        address: u16,
+        bytes: &[u8],
+        text: &str,
+        syntax: Syntax,
+        columns: bool,
+    ) -> String {
+        if !columns {
+            return format!("    {}\n", text);
+        }
+        let hex: String = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+        match syntax {
+            Syntax::Classic => format!("{:04X}  {:<16}  {}\n", address, hex, text),
+            Syntax::Octo => format!("    {:<32} # {:04X}  {}\n", text, address, hex),
+        }
+    }
+}
+
+/// Which label prefix wins when a target is referred to in several ways, lowest first.
+fn rank(name: &str) -> usize {
+    ["sub", "label", "data"]
+        .iter()
+        .position(|prefix| name.starts_with(prefix))
+        .unwrap_or(usize::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+    use crate::RAM_SIZE;
+
+    #[test]
+    fn skips_labels_inside_rendered_instructions() {
+        // call 0x203, V0 := 0 and a return at 0x203 that overlaps it
+        let rom = [0x22, 0x03, 0x60, 0x00, 0xEE];
+        let disassembly = Disassembly::new(&rom, Variant::Chip8);
+        assert!(disassembly.is_code(0x203));
+        assert!(disassembly.labels().is_empty());
+
+        let listing = disassembly.render(Syntax::Octo, false);
+        let assembly = assemble(&format!(": main\n{}", listing)).unwrap();
+        assert_eq!(assembly.rom, rom);
+    }
+
+    #[test]
+    fn leaves_out_bytes_past_ram() {
+        let rom = vec![0; 0x10000];
+        assert_eq!(Disassembly::new(&rom, Variant::Chip8).end(), RAM_SIZE);
+        let listing = Disassembly::new(&rom, Variant::XoChip).render(Syntax::Classic, true);
+        // 0000 decodes, so the whole ROM is one long run of instructions
+        assert!(listing.lines().last().unwrap().starts_with("FFFE"));
+    }
+}
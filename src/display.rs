@@ -0,0 +1,153 @@
+pub const SCREEN_WIDTH: usize = 64;
+pub const SCREEN_HEIGHT: usize = 32;
+
+/// size of the SUPER-CHIP high resolution mode
+pub const HIRES_SCREEN_WIDTH: usize = 128;
+pub const HIRES_SCREEN_HEIGHT: usize = 64;
+
+/// XO-CHIP has two bitplanes, giving four colors
+pub const NUM_PLANES: usize = 2;
+
+/// RGB colors the frontends draw with, indexed by `plane 0 | plane 1 << 1`.
+/// Only the first two are used without XO-CHIP bitplanes.
+pub const PALETTE: [[u8; 3]; 1 << NUM_PLANES] = [
+    [0x00, 0x00, 0x00],
+    [0x00, 0xff, 0x00],
+    [0xff, 0x00, 0xff],
+    [0xff, 0xff, 0xff],
+];
+
+/// The frame buffer, either 64x32 or 128x64 pixels, split into bitplanes.
+/// Plain CHIP-8 and SUPER-CHIP programs only ever draw to the first plane.
+pub(crate) struct Display {
+    high_res: bool,
+    planes: [Vec<bool>; NUM_PLANES],
+    /// bitmask of the planes drawing, clearing and scrolling apply to (XO-CHIP FN01)
+    selected: u8,
+}
+
+impl Display {
+    pub(crate) fn new() -> Self {
+        Self {
+            high_res: false,
+            planes: [
+                vec![false; SCREEN_WIDTH * SCREEN_HEIGHT],
+                vec![false; SCREEN_WIDTH * SCREEN_HEIGHT],
+            ],
+            selected: 1,
+        }
+    }
+
+    pub(crate) fn width(&self) -> usize {
+        if self.high_res {
+            HIRES_SCREEN_WIDTH
+        } else {
+            SCREEN_WIDTH
+        }
+    }
+
+    pub(crate) fn height(&self) -> usize {
+        if self.high_res {
+            HIRES_SCREEN_HEIGHT
+        } else {
+            SCREEN_HEIGHT
+        }
+    }
+
+    pub(crate) fn is_high_res(&self) -> bool {
+        self.high_res
+    }
+
+    pub(crate) fn plane(&self, plane: usize) -> &[bool] {
+        &self.planes[plane]
+    }
+
+    /// Replaces a plane's pixels, which must match the current resolution.
+    pub(crate) fn restore_plane(&mut self, plane: usize, pixels: Vec<bool>) {
+        debug_assert_eq!(pixels.len(), self.width() * self.height());
+        self.planes[plane] = pixels;
+    }
+
+    pub(crate) fn selected_planes(&self) -> u8 {
+        self.selected
+    }
+
+    pub(crate) fn select_planes(&mut self, mask: u8) {
+        self.selected = mask & 0b11;
+    }
+
+    /// Indices of the planes currently selected, in drawing order.
+    pub(crate) fn selected_plane_indices(&self) -> impl Iterator<Item = usize> {
+        let selected = self.selected;
+        (0..NUM_PLANES).filter(move |plane| selected & (1 << plane) != 0)
+    }
+
+    /// Switches resolution, which also clears every plane.
+    pub(crate) fn set_high_res(&mut self, high_res: bool) {
+        self.high_res = high_res;
+        let size = self.width() * self.height();
+        for plane in self.planes.iter_mut() {
+            *plane = vec![false; size];
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        for plane in self.selected_plane_indices() {
+            self.planes[plane].fill(false);
+        }
+    }
+
+    /// XORs a pixel on, returning true if it was already lit (a collision).
+    pub(crate) fn flip(&mut self, plane: usize, x: usize, y: usize) -> bool {
+        let index = x + y * self.width();
+        let pixels = &mut self.planes[plane];
+        let collided = pixels[index];
+        pixels[index] ^= true;
+        collided
+    }
+
+    pub(crate) fn scroll_down(&mut self, rows: usize) {
+        let width = self.width();
+        let height = self.height();
+        let rows = rows.min(height);
+        for plane in self.selected_plane_indices() {
+            let pixels = &mut self.planes[plane];
+            // move everything down and blank the rows that scrolled in at the top
+            pixels.copy_within(..(height - rows) * width, rows * width);
+            pixels[..rows * width].fill(false);
+        }
+    }
+
+    pub(crate) fn scroll_up(&mut self, rows: usize) {
+        let width = self.width();
+        let height = self.height();
+        let rows = rows.min(height);
+        for plane in self.selected_plane_indices() {
+            let pixels = &mut self.planes[plane];
+            pixels.copy_within(rows * width.., 0);
+            pixels[(height - rows) * width..].fill(false);
+        }
+    }
+
+    pub(crate) fn scroll_right(&mut self, columns: usize) {
+        let width = self.width();
+        let columns = columns.min(width);
+        for plane in self.selected_plane_indices() {
+            for row in self.planes[plane].chunks_mut(width) {
+                row.copy_within(..width - columns, columns);
+                row[..columns].fill(false);
+            }
+        }
+    }
+
+    pub(crate) fn scroll_left(&mut self, columns: usize) {
+        let width = self.width();
+        let columns = columns.min(width);
+        for plane in self.selected_plane_indices() {
+            for row in self.planes[plane].chunks_mut(width) {
+                row.copy_within(columns.., 0);
+                row[width - columns..].fill(false);
+            }
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use std::fmt;
+
+/// Faults the emulator can hit while executing a program.
+/// These are returned from `Emulator::tick` instead of panicking so a frontend
+/// can report the problem and decide whether to pause, skip or stop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmuError {
+    /// the opcode at `address` doesn't decode to any supported instruction
+    UnknownOpcode { address: u16, opcode: u16 },
+    /// a 2NNN call was made with a full stack
+    StackOverflow { address: u16 },
+    /// a 00EE return was made with an empty stack
+    StackUnderflow { address: u16 },
+    /// an instruction tried to read or write outside of RAM
+    MemoryOutOfBounds { address: u16, target: usize },
+    /// a key index outside of the 16 key keypad was used
+    InvalidKey { key: usize },
+    /// a V register index above VF was used
+    InvalidRegister { index: usize },
+    /// a debugger or tool pointed the emulator at an address outside of RAM
+    AddressOutOfRange { target: usize },
+    /// `Emulator::load` was given a ROM that doesn't fit above 0x200
+    RomTooLarge { size: usize, max: usize },
+    /// the program ran the SUPER-CHIP 00FD exit instruction
+    Exited { address: u16 },
+}
+
+impl fmt::Display for EmuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmuError::UnknownOpcode { address, opcode } => {
+                write!(f, "unknown opcode {:04X} at {:03X}", opcode, address)
+            }
+            EmuError::StackOverflow { address } => {
+                write!(f, "stack overflow at {:03X}", address)
+            }
+            EmuError::StackUnderflow { address } => {
+                write!(f, "stack underflow at {:03X}", address)
+            }
+            EmuError::MemoryOutOfBounds { address, target } => write!(
+                f,
+                "out of range memory access to {:#X} at {:03X}",
+                target, address
+            ),
+            EmuError::InvalidKey { key } => write!(f, "invalid key index {}", key),
+            EmuError::InvalidRegister { index } => write!(f, "invalid register index {}", index),
+            EmuError::AddressOutOfRange { target } => {
+                write!(f, "address {:#X} is outside of RAM", target)
+            }
+            EmuError::RomTooLarge { size, max } => {
+                write!(f, "ROM is {} bytes but at most {} fit in RAM", size, max)
+            }
+            EmuError::Exited { address } => write!(f, "program exited at {:03X}", address),
+        }
+    }
+}
+
+impl std::error::Error for EmuError {}
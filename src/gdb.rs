@@ -0,0 +1,529 @@
+//! A GDB remote serial protocol server, so GDB (or anything else speaking RSP) can debug a
+//! running emulator over TCP.
+//!
+//! The server owns a `Debugger` and runs the emulator through it: a frontend calls
+//! `GdbServer::run_frame` in place of `Emulator::run_frame`, once per frame, and the server
+//! answers packets, halts on breakpoints, watchpoints and Ctrl-C, and only runs the emulator
+//! while the client has it continuing. Without a client attached the emulator runs freely.
+//!
+//! Registers are numbered V0-VF (0-15), I (16), PC (17), SP (18), DT (19) and ST (20). The
+//! client gets them described in `target.xml`, 16 bit registers are sent little endian. SP is the
+//! number of return addresses on the stack and can't be written. Memory is the emulator's RAM.
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+
+use crate::debugger::{Command, Debugger, StopReason, WatchKind};
+use crate::{EmuError, Emulator, Registers};
+
+const NUM_REGISTERS: usize = 21;
+const REG_I: usize = 16;
+const REG_PC: usize = 17;
+const REG_SP: usize = 18;
+const REG_DT: usize = 19;
+const REG_ST: usize = 20;
+// sent as the ASCII byte 0x03 outside of a packet
+const INTERRUPT: u8 = 0x03;
+const MAX_PACKET_SIZE: usize = 0x4000;
+// a whole packet: `$`, up to MAX_PACKET_SIZE bytes of data, `#` and two checksum digits
+const MAX_FRAMED_SIZE: usize = MAX_PACKET_SIZE + 4;
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+
+fn target_xml() -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\"?>\n<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n\
+         <target version=\"1.0\">\n<feature name=\"org.chip8.core\">\n",
+    );
+    for index in 0..16 {
+        xml.push_str(&format!(
+            "<reg name=\"v{:x}\" bitsize=\"8\" regnum=\"{}\"/>\n",
+            index, index
+        ));
+    }
+    xml.push_str(
+        "<reg name=\"i\" bitsize=\"16\" type=\"data_ptr\"/>\n\
+         <reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\"/>\n\
+         <reg name=\"sp\" bitsize=\"8\"/>\n\
+         <reg name=\"dt\" bitsize=\"8\"/>\n\
+         <reg name=\"st\" bitsize=\"8\"/>\n\
+         </feature>\n</target>\n",
+    );
+    xml
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(text.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+fn parse_hex(text: &str) -> Option<usize> {
+    usize::from_str_radix(text, 16).ok()
+}
+
+/// Parses the `addr,length` that memory and breakpoint packets start with.
+fn parse_range(text: &str) -> Option<(usize, usize)> {
+    let (address, length) = text.split_once(',')?;
+    Some((parse_hex(address)?, parse_hex(length)?))
+}
+
+/// The bytes of a register as sent to the client, `None` if the value doesn't fit (an unlimited
+/// stack can be deeper than SP's one byte).
+fn register_bytes(registers: &Registers, index: usize) -> Option<Vec<u8>> {
+    Some(match index {
+        0..=15 => vec![registers.v[index]],
+        REG_I => registers.i.to_le_bytes().to_vec(),
+        REG_PC => registers.program_counter.to_le_bytes().to_vec(),
+        REG_SP => vec![u8::try_from(registers.stack_pointer).ok()?],
+        REG_DT => vec![registers.delay_timer],
+        _ => vec![registers.sound_timer],
+    })
+}
+
+/// Sets a register from the bytes a client sent, false if they're the wrong size or it's SP
+/// being changed.
+fn set_register(registers: &mut Registers, index: usize, bytes: &[u8]) -> bool {
+    match (index, bytes) {
+        (0..=15, &[value]) => registers.v[index] = value,
+        (REG_I, &[low, high]) => registers.i = u16::from_le_bytes([low, high]),
+        (REG_PC, &[low, high]) => registers.program_counter = u16::from_le_bytes([low, high]),
+        (REG_SP, &[value]) => return value as usize == registers.stack_pointer,
+        (REG_DT, &[value]) => registers.delay_timer = value,
+        (REG_ST, &[value]) => registers.sound_timer = value,
+        _ => return false,
+    }
+    true
+}
+
+/// The reply telling the client why the emulator stopped.
+fn stop_reply(reason: &StopReason) -> String {
+    match reason {
+        StopReason::Watchpoint { access, .. } => {
+            let kind = match access.kind {
+                crate::AccessKind::Read => "rwatch",
+                crate::AccessKind::Write => "watch",
+            };
+            format!("T{:02x}{}:{:x};", SIGTRAP, kind, access.address)
+        }
+        StopReason::Fault(EmuError::Exited { .. }) => "W00".to_string(),
+        StopReason::Fault(EmuError::UnknownOpcode { .. }) => format!("S{:02x}", SIGILL),
+        StopReason::Fault(_) => format!("S{:02x}", SIGSEGV),
+        _ => format!("S{:02x}", SIGTRAP),
+    }
+}
+
+struct Client {
+    stream: TcpStream,
+    // received bytes that don't make up a whole packet yet
+    buffer: Vec<u8>,
+}
+
+/// What a client sent, pulled out of the byte stream.
+enum Message {
+    Packet(String),
+    /// a packet with a bad checksum, which gets asked for again
+    Corrupt,
+    Interrupt,
+}
+
+impl Client {
+    /// Reads whatever has arrived without waiting. Returns false once the client has gone.
+    fn receive(&mut self) -> bool {
+        let mut chunk = [0; 1024];
+        // leave the rest in the socket until what's buffered has been handled
+        while self.buffer.len() < MAX_FRAMED_SIZE {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return false,
+                Ok(size) => self.buffer.extend_from_slice(&chunk[..size]),
+                Err(error) if error.kind() == ErrorKind::WouldBlock => return true,
+                Err(error) if error.kind() == ErrorKind::Interrupted => {}
+                Err(_) => return false,
+            }
+        }
+        true
+    }
+
+    fn next_message(&mut self) -> Option<Message> {
+        // acks and noise between packets
+        let start = self
+            .buffer
+            .iter()
+            .position(|&byte| byte == b'$' || byte == INTERRUPT)
+            .unwrap_or(self.buffer.len());
+        self.buffer.drain(..start);
+        if *self.buffer.first()? == INTERRUPT {
+            self.buffer.remove(0);
+            return Some(Message::Interrupt);
+        }
+        let end = match self
+            .buffer
+            .iter()
+            .take(MAX_FRAMED_SIZE - 2)
+            .position(|&byte| byte == b'#')
+        {
+            Some(end) => end,
+            // too long to be a packet, drop it and have the client resend
+            None if self.buffer.len() >= MAX_FRAMED_SIZE - 2 => {
+                self.buffer.clear();
+                return Some(Message::Corrupt);
+            }
+            None => return None,
+        };
+        if self.buffer.len() < end + 3 {
+            return None;
+        }
+        let packet: Vec<u8> = self.buffer.drain(..end + 3).collect();
+        let body = &packet[1..end];
+        let checksum = std::str::from_utf8(&packet[end + 1..])
+            .ok()
+            .and_then(|digits| u8::from_str_radix(digits, 16).ok());
+        let sum = body.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+        if checksum != Some(sum) {
+            return Some(Message::Corrupt);
+        }
+        Some(Message::Packet(String::from_utf8_lossy(body).into_owned()))
+    }
+
+    fn send_raw(&mut self, data: &[u8]) -> io::Result<()> {
+        // replies are small, so just block until they're written
+        self.stream.set_nonblocking(false)?;
+        let result = self.stream.write_all(data);
+        self.stream.set_nonblocking(true)?;
+        result
+    }
+
+    fn send(&mut self, packet: &str) -> io::Result<()> {
+        let sum = packet.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        self.send_raw(format!("${}#{:02x}", packet, sum).as_bytes())
+    }
+}
+
+pub struct GdbServer {
+    listener: TcpListener,
+    client: Option<Client>,
+    debugger: Debugger,
+    // the stop reply for why the emulator last stopped, repeated for the `?` packet
+    last_stop: String,
+}
+
+impl GdbServer {
+    /// Starts listening, e.g. on `127.0.0.1:9000`. The emulator runs freely until a client
+    /// attaches.
+    pub fn bind(address: impl ToSocketAddrs, emulator: &Emulator) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        let mut debugger = Debugger::new();
+        debugger.resume(emulator, Command::Continue);
+        Ok(Self {
+            listener,
+            client: None,
+            debugger,
+            last_stop: stop_reply(&StopReason::Step),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn is_attached(&self) -> bool {
+        self.client.is_some()
+    }
+
+    /// True while the emulator is stopped, waiting for the client.
+    pub fn is_halted(&self) -> bool {
+        !self.debugger.is_running()
+    }
+
+    /// Number of whole frames run, see `Debugger::frame`.
+    pub fn frame(&self) -> u64 {
+        self.debugger.frame()
+    }
+
+    /// Blocks until a client attaches, which halts the emulator.
+    pub fn wait_for_client(&mut self) -> io::Result<()> {
+        self.listener.set_nonblocking(false)?;
+        let result = self.listener.accept();
+        self.listener.set_nonblocking(true)?;
+        let (stream, _) = result?;
+        self.attach(stream)
+    }
+
+    /// Answers the client and, unless halted, runs up to a frame of instructions.
+    ///
+    /// A fault halts the emulator and is reported to the client. It's only returned when there's
+    /// no client to report it to, in which case the emulator stays halted until one attaches.
+    pub fn run_frame(&mut self, emulator: &mut Emulator) -> Result<(), EmuError> {
+        self.poll(emulator);
+        let ticks = emulator.config().ticks_per_frame;
+        let Some(reason) = self.debugger.run(emulator, ticks) else {
+            return Ok(());
+        };
+        let fault = match reason {
+            StopReason::Fault(error) => Some(error),
+            _ => None,
+        };
+        self.stopped(stop_reply(&reason));
+        match (fault, &self.client) {
+            (Some(error), None) => Err(error),
+            _ => Ok(()),
+        }
+    }
+
+    /// Accepts a client and answers its packets, without running the emulator.
+    pub fn poll(&mut self, emulator: &mut Emulator) {
+        if self.client.is_none() {
+            if let Ok((stream, _)) = self.listener.accept() {
+                if self.attach(stream).is_err() {
+                    self.client = None;
+                }
+            }
+        }
+        let Some(client) = &mut self.client else {
+            return;
+        };
+        if !client.receive() {
+            self.detach(emulator);
+            return;
+        }
+        while let Some(message) = self.client.as_mut().and_then(Client::next_message) {
+            let result = match message {
+                Message::Interrupt => {
+                    if self.debugger.is_running() {
+                        self.debugger.pause();
+                        self.stopped(format!("S{:02x}", SIGINT));
+                    }
+                    Ok(())
+                }
+                Message::Corrupt => self.send_raw(b"-"),
+                Message::Packet(packet) => self
+                    .send_raw(b"+")
+                    .and_then(|()| self.handle_packet(&packet, emulator)),
+            };
+            if result.is_err() {
+                self.detach(emulator);
+                return;
+            }
+        }
+    }
+
+    fn attach(&mut self, stream: TcpStream) -> io::Result<()> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        self.client = Some(Client {
+            stream,
+            buffer: Vec::new(),
+        });
+        // clients expect to find the target stopped
+        if self.debugger.is_running() {
+            self.debugger.pause();
+            self.last_stop = stop_reply(&StopReason::Step);
+        }
+        Ok(())
+    }
+
+    /// Drops the client and lets the emulator run freely again.
+    fn detach(&mut self, emulator: &Emulator) {
+        self.client = None;
+        self.debugger.resume(emulator, Command::Continue);
+    }
+
+    fn send(&mut self, packet: &str) -> io::Result<()> {
+        match &mut self.client {
+            Some(client) => client.send(packet),
+            None => Ok(()),
+        }
+    }
+
+    fn send_raw(&mut self, data: &[u8]) -> io::Result<()> {
+        match &mut self.client {
+            Some(client) => client.send_raw(data),
+            None => Ok(()),
+        }
+    }
+
+    /// Remembers why the emulator stopped and tells the client.
+    fn stopped(&mut self, reply: String) {
+        if self.send(&reply).is_err() {
+            self.client = None;
+        }
+        self.last_stop = reply;
+    }
+
+    fn handle_packet(&mut self, packet: &str, emulator: &mut Emulator) -> io::Result<()> {
+        let (command, rest) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+        let reply = match command {
+            "?" => self.last_stop.clone(),
+            "g" => {
+                let registers = emulator.registers();
+                (0..NUM_REGISTERS)
+                    .map(|index| register_bytes(&registers, index).map(|bytes| to_hex(&bytes)))
+                    .collect::<Option<String>>()
+                    .unwrap_or_else(|| "E01".to_string())
+            }
+            "G" => self.write_registers(rest, emulator),
+            "p" => match parse_hex(rest)
+                .filter(|&index| index < NUM_REGISTERS)
+                .and_then(|index| register_bytes(&emulator.registers(), index))
+            {
+                Some(bytes) => to_hex(&bytes),
+                None => "E01".to_string(),
+            },
+            "P" => self.write_register(rest, emulator),
+            "m" => match parse_range(rest) {
+                Some((address, length)) if length <= MAX_PACKET_SIZE / 2 => {
+                    match emulator.read_memory(address, length) {
+                        Some(bytes) => to_hex(bytes),
+                        None => "E01".to_string(),
+                    }
+                }
+                _ => "E01".to_string(),
+            },
+            "M" => {
+                let written = rest.split_once(':').and_then(|(range, data)| {
+                    let (address, length) =
+                        parse_range(range).filter(|&(_, length)| length <= MAX_PACKET_SIZE / 2)?;
+                    let data = from_hex(data).filter(|data| data.len() == length)?;
+                    emulator.write_memory(address, &data).ok()
+                });
+                match written {
+                    Some(()) => "OK".to_string(),
+                    None => "E01".to_string(),
+                }
+            }
+            "c" => {
+                self.debugger.resume(emulator, Command::Continue);
+                // the stop reply goes out when it stops
+                return Ok(());
+            }
+            "s" => {
+                self.debugger.resume(emulator, Command::StepInto);
+                let reason = self.debugger.run(emulator, 1).unwrap_or(StopReason::Step);
+                self.stopped(stop_reply(&reason));
+                return Ok(());
+            }
+            "Z" | "z" => self.change_point(command == "Z", rest, emulator.ram().len()),
+            "D" => {
+                self.send("OK")?;
+                self.detach(emulator);
+                return Ok(());
+            }
+            "k" => {
+                self.detach(emulator);
+                return Ok(());
+            }
+            "H" => "OK".to_string(),
+            "q" => self.query(rest),
+            // anything else is unsupported, which is an empty reply
+            _ => String::new(),
+        };
+        self.send(&reply)
+    }
+
+    fn write_registers(&mut self, rest: &str, emulator: &mut Emulator) -> String {
+        let Some(bytes) = from_hex(rest) else {
+            return "E01".to_string();
+        };
+        let mut registers = emulator.registers();
+        let mut bytes = bytes.as_slice();
+        for index in 0..NUM_REGISTERS {
+            let Some(size) = register_bytes(&registers, index).map(|bytes| bytes.len()) else {
+                return "E01".to_string();
+            };
+            if bytes.len() < size || !set_register(&mut registers, index, &bytes[..size]) {
+                return "E01".to_string();
+            }
+            bytes = &bytes[size..];
+        }
+        match emulator.set_registers(&registers) {
+            Ok(()) => "OK".to_string(),
+            Err(_) => "E01".to_string(),
+        }
+    }
+
+    fn write_register(&mut self, rest: &str, emulator: &mut Emulator) -> String {
+        let mut registers = emulator.registers();
+        let written = rest.split_once('=').and_then(|(index, value)| {
+            let index = parse_hex(index)?;
+            set_register(&mut registers, index, &from_hex(value)?).then_some(())
+        });
+        match written.map(|()| emulator.set_registers(&registers)) {
+            Some(Ok(())) => "OK".to_string(),
+            _ => "E01".to_string(),
+        }
+    }
+
+    /// `Z`/`z` packets: type 0 and 1 are breakpoints, 2-4 write, read and access watchpoints.
+    fn change_point(&mut self, insert: bool, rest: &str, ram_size: usize) -> String {
+        let Some((kind, range)) = rest.split_once(',') else {
+            return "E01".to_string();
+        };
+        // breakpoints may have conditions after a ;, which aren't supported
+        let Some((address, length)) = parse_range(range.split(';').next().unwrap_or("")) else {
+            return "E01".to_string();
+        };
+        let watch = match kind {
+            "0" | "1" => {
+                let Ok(address) = u16::try_from(address) else {
+                    return "E01".to_string();
+                };
+                if insert {
+                    self.debugger.add_breakpoint(address);
+                } else {
+                    self.debugger.remove_breakpoint(address);
+                }
+                return "OK".to_string();
+            }
+            "2" => WatchKind::Write,
+            "3" => WatchKind::Read,
+            "4" => WatchKind::Access,
+            _ => return String::new(),
+        };
+        if address >= ram_size {
+            return "E01".to_string();
+        }
+        for byte in address..address.saturating_add(length).min(ram_size) {
+            if insert {
+                self.debugger.add_watchpoint(byte, watch);
+            } else {
+                self.debugger.remove_watchpoint(byte);
+            }
+        }
+        "OK".to_string()
+    }
+
+    fn query(&mut self, rest: &str) -> String {
+        if rest.starts_with("Supported") {
+            return format!("PacketSize={:x};qXfer:features:read+", MAX_PACKET_SIZE);
+        }
+        if let Some(request) = rest.strip_prefix("Xfer:features:read:target.xml:") {
+            let Some((offset, length)) = parse_range(request) else {
+                return "E01".to_string();
+            };
+            let xml = target_xml();
+            let start = offset.min(xml.len());
+            let end = start.saturating_add(length).min(xml.len());
+            let marker = if end == xml.len() { 'l' } else { 'm' };
+            return format!("{}{}", marker, &xml[start..end]);
+        }
+        match rest {
+            "Attached" => "1".to_string(),
+            "C" => "QC1".to_string(),
+            "fThreadInfo" => "m1".to_string(),
+            "sThreadInfo" => "l".to_string(),
+            _ => String::new(),
+        }
+    }
+}
@@ -0,0 +1,119 @@
+//! A heatmap of RAM activity: every byte of the 4K address space is a cell in a 64x64 image, lit
+//! red when written, green when read and blue when run as an instruction.
+//!
+//! Hand the emulator a `Heatmap` with `Emulator::set_heatmap` and every instruction `tick` runs
+//! lights the cells it touched. The light fades a little every frame, when the timers tick, so
+//! the picture shows what the program is doing now, while anything touched since the heatmap
+//! started stays dimly lit. XO-CHIP's RAM above 4K isn't shown.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::screenshot::encode_png;
+use crate::{AccessKind, MemoryAccess};
+
+/// Cells along each side of the heatmap.
+pub const HEATMAP_SIZE: usize = 64;
+const CELLS: usize = HEATMAP_SIZE * HEATMAP_SIZE;
+// how much of the light is left after each frame, about a second to fade out
+const FADE: f32 = 0.94;
+// brightness of a cell that was touched at some point but has faded
+const TOUCHED_LEVEL: f32 = 0.15;
+
+const READ: usize = 0;
+const WRITE: usize = 1;
+const EXECUTE: usize = 2;
+
+pub struct Heatmap {
+    // light of each cell for reads, writes and executes, from 0 to 1
+    levels: [Vec<f32>; 3],
+    touched: [Vec<bool>; 3],
+}
+
+impl Default for Heatmap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Heatmap {
+    pub fn new() -> Self {
+        Self {
+            levels: [vec![0.0; CELLS], vec![0.0; CELLS], vec![0.0; CELLS]],
+            touched: [vec![false; CELLS], vec![false; CELLS], vec![false; CELLS]],
+        }
+    }
+
+    fn light(&mut self, kind: usize, address: usize) {
+        if address < CELLS {
+            self.levels[kind][address] = 1.0;
+            self.touched[kind][address] = true;
+        }
+    }
+
+    /// Lights the `size` bytes of an instruction `tick` ran and the RAM it accessed.
+    pub(crate) fn record(&mut self, address: u16, size: usize, accesses: &[MemoryAccess]) {
+        for byte in address as usize..address as usize + size {
+            self.light(EXECUTE, byte);
+        }
+        for access in accesses {
+            let kind = match access.kind {
+                AccessKind::Read => READ,
+                AccessKind::Write => WRITE,
+            };
+            self.light(kind, access.address);
+        }
+    }
+
+    /// Dims every cell, called once a frame.
+    pub(crate) fn fade(&mut self) {
+        for levels in &mut self.levels {
+            for level in levels.iter_mut() {
+                *level *= FADE;
+            }
+        }
+    }
+
+    /// Color of the cell for `address`, as RGB.
+    pub fn color(&self, address: usize) -> [u8; 3] {
+        let channel = |kind: usize| {
+            let mut level = self.levels[kind][address];
+            if self.touched[kind][address] {
+                level = level.max(TOUCHED_LEVEL);
+            }
+            (level * 255.0) as u8
+        };
+        [channel(WRITE), channel(READ), channel(EXECUTE)]
+    }
+
+    /// The heatmap as RGB bytes, address 0 top left and a row per 64 bytes, each cell a `scale`
+    /// by `scale` square. Returns the width and height along with the pixels.
+    pub fn rgb(&self, scale: usize) -> (usize, usize, Vec<u8>) {
+        let scale = scale.max(1);
+        let size = HEATMAP_SIZE * scale;
+        let mut pixels = Vec::with_capacity(size * size * 3);
+        for row in 0..HEATMAP_SIZE {
+            let mut line = Vec::with_capacity(size * 3);
+            for column in 0..HEATMAP_SIZE {
+                let color = self.color(row * HEATMAP_SIZE + column);
+                for _ in 0..scale {
+                    line.extend_from_slice(&color);
+                }
+            }
+            for _ in 0..scale {
+                pixels.extend_from_slice(&line);
+            }
+        }
+        (size, size, pixels)
+    }
+
+    pub fn png(&self, scale: usize) -> Vec<u8> {
+        let (width, height, pixels) = self.rgb(scale);
+        encode_png(width, height, &pixels)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>, scale: usize) -> io::Result<()> {
+        fs::write(path, self.png(scale))
+    }
+}
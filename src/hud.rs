@@ -0,0 +1,245 @@
+//! A debug panel for the SDL frontend: registers, the stack, timers, the next few instructions
+//! and the keypad, drawn next to the game with a built in 5x7 font so it needs no font files.
+
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+
+use crate::disasm::{Op, Syntax};
+use crate::Emulator;
+
+const GLYPH_WIDTH: usize = 5;
+const GLYPH_HEIGHT: usize = 7;
+// each font pixel is this many screen pixels
+const FONT_SCALE: u32 = 2;
+// a glyph plus a pixel of space on the right and below
+const CHAR_WIDTH: u32 = (GLYPH_WIDTH as u32 + 1) * FONT_SCALE;
+const LINE_HEIGHT: u32 = (GLYPH_HEIGHT as u32 + 2) * FONT_SCALE;
+const MARGIN: u32 = 8;
+// characters per line, enough for a register row or an address and instruction
+const COLUMNS: u32 = 24;
+/// Width of the panel in pixels.
+pub const HUD_WIDTH: u32 = COLUMNS * CHAR_WIDTH + 2 * MARGIN;
+// instructions shown from the program counter on
+const INSTRUCTIONS: usize = 6;
+// return addresses shown, innermost first
+const STACK_LINES: usize = 4;
+// the keypad as it's laid out on the COSMAC VIP
+const KEYPAD_ROWS: [[usize; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xc],
+    [0x4, 0x5, 0x6, 0xd],
+    [0x7, 0x8, 0x9, 0xe],
+    [0xa, 0x0, 0xb, 0xf],
+];
+
+const BACKGROUND: Color = Color::RGB(0x20, 0x20, 0x20);
+const TEXT: Color = Color::RGB(0xc0, 0xc0, 0xc0);
+// the instruction about to run and pressed keys
+const HIGHLIGHT: Color = Color::RGB(0xff, 0xd0, 0x40);
+
+// rows of each glyph from ' ' to '_', the top bit of the five is the left pixel
+const FONT: [[u8; GLYPH_HEIGHT]; 64] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // space
+    [0x04, 0x04, 0x04, 0x04, 0x04, 0x00, 0x04], // !
+    [0x0a, 0x0a, 0x00, 0x00, 0x00, 0x00, 0x00], // "
+    [0x0a, 0x0a, 0x1f, 0x0a, 0x1f, 0x0a, 0x0a], // #
+    [0x04, 0x0f, 0x14, 0x0e, 0x05, 0x1e, 0x04], // $
+    [0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03], // %
+    [0x0c, 0x12, 0x14, 0x08, 0x15, 0x12, 0x0d], // &
+    [0x04, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00], // '
+    [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02], // (
+    [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08], // )
+    [0x00, 0x04, 0x15, 0x0e, 0x15, 0x04, 0x00], // *
+    [0x00, 0x04, 0x04, 0x1f, 0x04, 0x04, 0x00], // +
+    [0x00, 0x00, 0x00, 0x00, 0x0c, 0x04, 0x08], // ,
+    [0x00, 0x00, 0x00, 0x1f, 0x00, 0x00, 0x00], // -
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x0c], // .
+    [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00], // /
+    [0x0e, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0e], // 0
+    [0x04, 0x0c, 0x04, 0x04, 0x04, 0x04, 0x0e], // 1
+    [0x0e, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1f], // 2
+    [0x1f, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0e], // 3
+    [0x02, 0x06, 0x0a, 0x12, 0x1f, 0x02, 0x02], // 4
+    [0x1f, 0x10, 0x1e, 0x01, 0x01, 0x11, 0x0e], // 5
+    [0x06, 0x08, 0x10, 0x1e, 0x11, 0x11, 0x0e], // 6
+    [0x1f, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08], // 7
+    [0x0e, 0x11, 0x11, 0x0e, 0x11, 0x11, 0x0e], // 8
+    [0x0e, 0x11, 0x11, 0x0f, 0x01, 0x02, 0x0c], // 9
+    [0x00, 0x0c, 0x0c, 0x00, 0x0c, 0x0c, 0x00], // :
+    [0x00, 0x0c, 0x0c, 0x00, 0x0c, 0x04, 0x08], // ;
+    [0x02, 0x04, 0x08, 0x10, 0x08, 0x04, 0x02], // <
+    [0x00, 0x00, 0x1f, 0x00, 0x1f, 0x00, 0x00], // =
+    [0x08, 0x04, 0x02, 0x01, 0x02, 0x04, 0x08], // >
+    [0x0e, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04], // ?
+    [0x0e, 0x11, 0x01, 0x0d, 0x15, 0x15, 0x0e], // @
+    [0x0e, 0x11, 0x11, 0x11, 0x1f, 0x11, 0x11], // A
+    [0x1e, 0x11, 0x11, 0x1e, 0x11, 0x11, 0x1e], // B
+    [0x0e, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0e], // C
+    [0x1c, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1c], // D
+    [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x1f], // E
+    [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x10], // F
+    [0x0e, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0f], // G
+    [0x11, 0x11, 0x11, 0x1f, 0x11, 0x11, 0x11], // H
+    [0x0e, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0e], // I
+    [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0c], // J
+    [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11], // K
+    [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1f], // L
+    [0x11, 0x1b, 0x15, 0x15, 0x11, 0x11, 0x11], // M
+    [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11], // N
+    [0x0e, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e], // O
+    [0x1e, 0x11, 0x11, 0x1e, 0x10, 0x10, 0x10], // P
+    [0x0e, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0d], // Q
+    [0x1e, 0x11, 0x11, 0x1e, 0x14, 0x12, 0x11], // R
+    [0x0f, 0x10, 0x10, 0x0e, 0x01, 0x01, 0x1e], // S
+    [0x1f, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04], // T
+    [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e], // U
+    [0x11, 0x11, 0x11, 0x11, 0x11, 0x0a, 0x04], // V
+    [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0a], // W
+    [0x11, 0x11, 0x0a, 0x04, 0x0a, 0x11, 0x11], // X
+    [0x11, 0x11, 0x11, 0x0a, 0x04, 0x04, 0x04], // Y
+    [0x1f, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1f], // Z
+    [0x0e, 0x08, 0x08, 0x08, 0x08, 0x08, 0x0e], // [
+    [0x00, 0x10, 0x08, 0x04, 0x02, 0x01, 0x00], // backslash
+    [0x0e, 0x02, 0x02, 0x02, 0x02, 0x02, 0x0e], // ]
+    [0x04, 0x0a, 0x11, 0x00, 0x00, 0x00, 0x00], // ^
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1f], // _
+];
+// the only lower case letter that shows up, in 0x prefixes
+const SMALL_X: [u8; GLYPH_HEIGHT] = [0x00, 0x00, 0x11, 0x0a, 0x04, 0x0a, 0x11];
+
+fn glyph(c: char) -> &'static [u8; GLYPH_HEIGHT] {
+    if c == 'x' {
+        return &SMALL_X;
+    }
+    let index = (c.to_ascii_uppercase() as usize).wrapping_sub(' ' as usize);
+    FONT.get(index)
+        .unwrap_or(&FONT['?' as usize - ' ' as usize])
+}
+
+/// Adds a rect for every lit font pixel of `text`, starting at `x`, `y`.
+fn text_rects(rects: &mut Vec<Rect>, text: &str, x: i32, y: i32) {
+    for (column, c) in text.chars().take(COLUMNS as usize).enumerate() {
+        let left = x + (column as u32 * CHAR_WIDTH) as i32;
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for pixel in 0..GLYPH_WIDTH {
+                if bits & (0x10 >> pixel) != 0 {
+                    rects.push(Rect::new(
+                        left + (pixel as u32 * FONT_SCALE) as i32,
+                        y + (row as u32 * FONT_SCALE) as i32,
+                        FONT_SCALE,
+                        FONT_SCALE,
+                    ));
+                }
+            }
+        }
+    }
+}
+
+/// The panel's lines, each with whether it's highlighted. Keypad lines are left out, they're
+/// drawn a key at a time.
+fn lines(emulator: &Emulator) -> Vec<(String, bool)> {
+    let registers = emulator.registers();
+    let mut lines = vec![
+        (
+            format!(
+                "PC {:04X}  I {:04X}",
+                registers.program_counter, registers.i
+            ),
+            false,
+        ),
+        (
+            format!(
+                "SP {:<2}  DT {:02X}  ST {:02X}",
+                registers.stack_pointer, registers.delay_timer, registers.sound_timer
+            ),
+            false,
+        ),
+    ];
+    for (row, values) in registers.v.chunks(4).enumerate() {
+        let line: Vec<String> = values
+            .iter()
+            .enumerate()
+            .map(|(column, value)| format!("V{:X} {:02X}", row * 4 + column, value))
+            .collect();
+        lines.push((line.join(" "), false));
+    }
+
+    lines.push((String::new(), false));
+    lines.push(("STACK".to_string(), false));
+    let stack = emulator.call_stack();
+    for address in stack.iter().rev().take(STACK_LINES) {
+        lines.push((format!(" {:04X}", address), false));
+    }
+    if stack.len() > STACK_LINES {
+        lines.push((" ...".to_string(), false));
+    }
+    if stack.is_empty() {
+        lines.push((" -".to_string(), false));
+    }
+
+    lines.push((String::new(), false));
+    let ram = emulator.ram();
+    let variant = emulator.config().variant;
+    let mut address = registers.program_counter as usize;
+    for index in 0..INSTRUCTIONS {
+        let Some(bytes) = ram.get(address..) else {
+            break;
+        };
+        let op = Op::decode(bytes, variant);
+        let text = match op {
+            Some(op) => op.text(Syntax::Classic),
+            None => format!(
+                "DW 0x{:02X}{:02X}",
+                bytes.first().copied().unwrap_or(0),
+                bytes.get(1).copied().unwrap_or(0)
+            ),
+        };
+        lines.push((format!("{:04X} {}", address, text), index == 0));
+        address += op.map_or(2, |op| op.size() as usize);
+    }
+
+    lines.push((String::new(), false));
+    lines.push(("KEYS".to_string(), false));
+    lines
+}
+
+/// Draws the panel at `x`, the full height of the canvas, updated from `emulator`.
+pub fn draw(emulator: &Emulator, canvas: &mut Canvas<Window>, x: i32) {
+    let (_, height) = canvas.output_size().unwrap_or((0, 0));
+    canvas.set_draw_color(BACKGROUND);
+    // drawing only fails if the renderer is gone, and then there's nothing to draw on anyway
+    let _ = canvas.fill_rect(Rect::new(x, 0, HUD_WIDTH, height));
+
+    let left = x + MARGIN as i32;
+    let mut text = Vec::new();
+    let mut highlighted = Vec::new();
+    let mut y = MARGIN as i32;
+    for (line, highlight) in lines(emulator) {
+        let rects = if highlight {
+            &mut highlighted
+        } else {
+            &mut text
+        };
+        text_rects(rects, &line, left, y);
+        y += LINE_HEIGHT as i32;
+    }
+    let pressed = emulator.pressed_keys();
+    for row in KEYPAD_ROWS {
+        for (column, key) in row.into_iter().enumerate() {
+            let rects = if pressed & (1 << key) != 0 {
+                &mut highlighted
+            } else {
+                &mut text
+            };
+            let key_x = left + ((1 + column as u32 * 2) * CHAR_WIDTH) as i32;
+            text_rects(rects, &format!("{:X}", key), key_x, y);
+        }
+        y += LINE_HEIGHT as i32;
+    }
+
+    canvas.set_draw_color(TEXT);
+    let _ = canvas.fill_rects(&text);
+    canvas.set_draw_color(HIGHLIGHT);
+    let _ = canvas.fill_rects(&highlighted);
+}
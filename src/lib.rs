@@ -1,14 +1,63 @@
+pub mod assembler;
+pub mod audio;
+mod config;
+pub mod coverage;
+pub mod debugger;
+pub mod disasm;
+mod display;
+mod error;
+pub mod gdb;
+pub mod heatmap;
+#[cfg(feature = "sdl")]
+pub mod hud;
+pub mod movie;
+pub mod profile;
+mod quirks;
+pub mod rewind;
+mod rng;
+pub mod screenshot;
+#[cfg(feature = "sdl")]
 pub mod sound;
+pub mod state;
+pub mod trace;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod video;
 
+pub use config::{parse_number, Config, MemoryPolicy, StackConfig, StackOverflowBehavior, Variant};
+use coverage::Coverage;
+use display::Display;
+pub use display::{
+    HIRES_SCREEN_HEIGHT, HIRES_SCREEN_WIDTH, NUM_PLANES, PALETTE, SCREEN_HEIGHT, SCREEN_WIDTH,
+};
+pub use error::EmuError;
+use heatmap::Heatmap;
+use profile::Profiler;
+pub use quirks::{LoadStoreQuirk, Quirks};
 use rand::random;
-
-pub const SCREEN_WIDTH: usize = 64;
-pub const SCREEN_HEIGHT: usize = 32;
+pub use rng::{RandomSource, XorShiftRng};
+pub use state::StateError;
+use trace::Tracer;
 
 const RAM_SIZE: usize = 4096;
+// XO-CHIP programs get the full 16 bit address space
+const XO_CHIP_RAM_SIZE: usize = 0x10000;
 const NUM_REGISTERS: usize = 16;
-const STACK_SIZE: usize = 16;
+// the COSMAC VIP interpreter keeps its call stack just below 0xED0, growing downwards
+const VIP_STACK_TOP: usize = 0xED0;
 const NUM_KEYS: usize = 16;
+// SUPER-CHIP's RPL user flags, saved and restored by FX75/FX85
+// SUPER-CHIP has 8 of them and XO-CHIP 16
+const NUM_FLAGS: usize = 16;
+const SUPERCHIP_NUM_FLAGS: usize = 8;
+// XO-CHIP audio, a 128 bit pattern played back at a rate set by the pitch register
+pub const AUDIO_PATTERN_SIZE: usize = 16;
+const DEFAULT_PITCH: u8 = 64;
+
+/// Playback rate of the XO-CHIP audio pattern in bits per second for a pitch register value.
+pub fn audio_pattern_rate(pitch: u8) -> f32 {
+    4000.0 * 2f32.powf((pitch as f32 - 64.0) / 48.0)
+}
 
 const START_ADDR: u16 = 0x200;
 
@@ -32,93 +81,553 @@ const FONTSET: [u8; FONTSET_SIZE] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
+// SUPER-CHIP 8x10 font for FX30, stored straight after the small font
+const BIG_FONTSET_ADDR: usize = FONTSET_SIZE;
+const BIG_FONTSET_SIZE: usize = 160;
+const BIG_FONTSET: [u8; BIG_FONTSET_SIZE] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+/// Describes the instruction executed by a successful `Emulator::tick`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StepInfo {
+    /// address the instruction was fetched from
+    pub address: u16,
+    /// the raw opcode
+    pub opcode: u16,
+}
+
+/// A copy of the CPU registers and timers, see `Emulator::registers`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Registers {
+    pub program_counter: u16,
+    pub i: u16,
+    pub v: [u8; NUM_REGISTERS],
+    /// number of return addresses on the stack
+    pub stack_pointer: usize,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+}
+
+/// Whether a `MemoryAccess` read or wrote RAM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+/// A byte of RAM an instruction read or wrote, see `Emulator::last_accesses`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryAccess {
+    /// RAM address, after the memory policy was applied
+    pub address: usize,
+    pub kind: AccessKind,
+    /// the byte read, or the byte written
+    pub value: u8,
+}
+
 pub struct Emulator {
+    config: Config,
     program_counter: u16,
-    ram: [u8; RAM_SIZE],
-    screen: [bool; SCREEN_WIDTH * SCREEN_HEIGHT],
+    ram: Vec<u8>,
+    display: Display,
     v_register: [u8; NUM_REGISTERS],
     i_register: u16,
-    stack_pointer: u16,
-    stack: [u16; STACK_SIZE],
+    stack_pointer: usize,
+    stack: Vec<u16>,
     keys: [bool; NUM_KEYS],
     delay_timer: u8,
     sound_timer: u8,
+    vblank: bool,
+    flags: [u8; NUM_FLAGS],
+    audio_pattern: Option<[u8; AUDIO_PATTERN_SIZE]>,
+    pitch: u8,
+    seed: u64,
+    rng: Box<dyn RandomSource>,
+    // RAM accessed by the last instruction, for debuggers
+    accesses: Vec<MemoryAccess>,
+    tracer: Option<Tracer>,
+    profiler: Option<Profiler>,
+    coverage: Option<Coverage>,
+    heatmap: Option<Heatmap>,
+}
+
+impl Default for Emulator {
+    fn default() -> Self {
+        Self::new()
+    }
 }
+
 impl Emulator {
     pub fn new() -> Self {
+        Self::with_config(Config::default())
+    }
+
+    pub fn with_quirks(quirks: Quirks) -> Self {
+        Self::with_config(Config {
+            quirks,
+            ..Config::default()
+        })
+    }
+
+    pub fn with_config(config: Config) -> Self {
+        // without a configured seed every run gets a different sequence
+        let seed = config.seed.unwrap_or_else(random);
         let mut emulator = Self {
+            config,
             program_counter: START_ADDR,
-            ram: [0; RAM_SIZE],
-            screen: [false; SCREEN_WIDTH * SCREEN_HEIGHT],
+            ram: vec![0; config.variant.ram_size()],
+            display: Display::new(),
             v_register: [0; NUM_REGISTERS],
             i_register: 0,
             stack_pointer: 0,
-            stack: [0; STACK_SIZE],
+            stack: vec![0; config.stack.depth.unwrap_or(0)],
             keys: [false; NUM_KEYS],
             delay_timer: 0,
             sound_timer: 0,
+            vblank: false,
+            flags: [0; NUM_FLAGS],
+            audio_pattern: None,
+            pitch: DEFAULT_PITCH,
+            seed,
+            rng: Box::new(XorShiftRng::new(seed)),
+            accesses: Vec::new(),
+            tracer: None,
+            profiler: None,
+            coverage: None,
+            heatmap: None,
         };
         emulator.ram[..FONTSET_SIZE].copy_from_slice(&FONTSET);
+        emulator.ram[BIG_FONTSET_ADDR..BIG_FONTSET_ADDR + BIG_FONTSET_SIZE]
+            .copy_from_slice(&BIG_FONTSET);
         emulator
     }
 
-    fn push(&mut self, value: u16) {
-        self.stack[self.stack_pointer as usize] = value;
+    fn push(&mut self, address: u16, value: u16) -> Result<(), EmuError> {
+        if let Some(depth) = self
+            .config
+            .stack
+            .depth
+            .filter(|&depth| depth == self.stack_pointer)
+        {
+            match self.config.stack.overflow {
+                // a stack with no slots has nowhere to wrap to
+                StackOverflowBehavior::Wrap if depth > 0 => self.stack_pointer = 0,
+                StackOverflowBehavior::Ignore => return Ok(()),
+                _ => return Err(EmuError::StackOverflow { address }),
+            }
+        }
+        self.write_stack_slot(address, self.stack_pointer, value)?;
         self.stack_pointer += 1;
+        Ok(())
     }
 
-    fn pop(&mut self) -> u16 {
+    /// Pops a return address, `None` means the return should be ignored.
+    fn pop(&mut self, address: u16) -> Result<Option<u16>, EmuError> {
+        if self.stack_pointer == 0 {
+            match (self.config.stack.overflow, self.config.stack.depth) {
+                (StackOverflowBehavior::Wrap, Some(depth)) if depth > 0 => {
+                    self.stack_pointer = depth
+                }
+                (StackOverflowBehavior::Ignore, _) => return Ok(None),
+                _ => return Err(EmuError::StackUnderflow { address }),
+            }
+        }
         self.stack_pointer -= 1;
-        self.stack[self.stack_pointer as usize]
+        self.read_stack_slot(address, self.stack_pointer).map(Some)
+    }
+
+    /// RAM address of the high byte of a stack slot when the stack lives in RAM.
+    fn stack_slot_address(address: u16, slot: usize) -> Result<usize, EmuError> {
+        VIP_STACK_TOP
+            .checked_sub(2 * (slot + 1))
+            .ok_or(EmuError::StackOverflow { address })
+    }
+
+    fn read_stack_slot(&mut self, address: u16, slot: usize) -> Result<u16, EmuError> {
+        if self.config.stack.in_ram {
+            let target = Self::stack_slot_address(address, slot)?;
+            let upper_byte = self.read_ram(address, target)? as u16;
+            let lower_byte = self.read_ram(address, target + 1)? as u16;
+            Ok((upper_byte << 8) | lower_byte)
+        } else {
+            Ok(self.stack[slot])
+        }
+    }
+
+    fn write_stack_slot(&mut self, address: u16, slot: usize, value: u16) -> Result<(), EmuError> {
+        if self.config.stack.in_ram {
+            let target = Self::stack_slot_address(address, slot)?;
+            self.write_ram(address, target, (value >> 8) as u8)?;
+            self.write_ram(address, target + 1, value as u8)
+        } else {
+            if slot < self.stack.len() {
+                self.stack[slot] = value;
+            } else {
+                // only an unlimited stack grows
+                self.stack.push(value);
+            }
+            Ok(())
+        }
+    }
+
+    /// Applies the memory policy to a RAM address.
+    /// `address` is the instruction doing the access and `target` is the byte being accessed.
+    fn resolve(&self, address: u16, target: usize) -> Result<usize, EmuError> {
+        match self.config.memory_policy {
+            MemoryPolicy::Wrap => Ok(target % self.ram.len()),
+            MemoryPolicy::Fault if target < self.ram.len() => Ok(target),
+            MemoryPolicy::Fault => Err(EmuError::MemoryOutOfBounds { address, target }),
+        }
+    }
+
+    fn read_ram(&mut self, address: u16, target: usize) -> Result<u8, EmuError> {
+        let target = self.resolve(address, target)?;
+        let value = self.ram[target];
+        self.accesses.push(MemoryAccess {
+            address: target,
+            kind: AccessKind::Read,
+            value,
+        });
+        Ok(value)
+    }
+
+    fn write_ram(&mut self, address: u16, target: usize, value: u8) -> Result<(), EmuError> {
+        let target = self.resolve(address, target)?;
+        self.ram[target] = value;
+        self.accesses.push(MemoryAccess {
+            address: target,
+            kind: AccessKind::Write,
+            value,
+        });
+        Ok(())
+    }
+
+    fn key(&self, key: usize) -> Result<bool, EmuError> {
+        // the keypad only decodes the low nibble, so wrapping keeps the lowest 4 bits
+        let key = match self.config.memory_policy {
+            MemoryPolicy::Wrap => key % NUM_KEYS,
+            MemoryPolicy::Fault => key,
+        };
+        self.keys
+            .get(key)
+            .copied()
+            .ok_or(EmuError::InvalidKey { key })
     }
 
     #[allow(dead_code)]
     fn reset(&mut self) {
-        self.program_counter = START_ADDR;
-        self.ram = [0; RAM_SIZE];
-        self.screen = [false; SCREEN_WIDTH * SCREEN_HEIGHT];
-        self.v_register = [0; NUM_REGISTERS];
-        self.i_register = 0;
-        self.stack_pointer = 0;
-        self.stack = [0; STACK_SIZE];
-        self.keys = [false; NUM_KEYS];
-        self.delay_timer = 0;
-        self.sound_timer = 0;
-        self.ram[..FONTSET_SIZE].copy_from_slice(&FONTSET);
-    }
-
-    pub fn tick(&mut self) {
+        // restart with the same seed so a reset replays the same random sequence
+        *self = Self::with_config(Config {
+            seed: Some(self.seed),
+            ..self.config
+        });
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// The seed the random number generator started from, set with `Config::seed`.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Replaces the random number generator used by CXNN.
+    pub fn set_random_source(&mut self, rng: Box<dyn RandomSource>) {
+        self.rng = rng;
+    }
+
+    pub fn random_state(&self) -> u64 {
+        self.rng.state()
+    }
+
+    pub fn set_random_state(&mut self, state: u64) {
+        self.rng.set_state(state);
+    }
+
+    /// Executes a single instruction.
+    /// On a fault the program counter is left pointing at the faulting instruction.
+    pub fn tick(&mut self) -> Result<StepInfo, EmuError> {
+        let address = self.program_counter;
+        let stack_before = self.stack_pointer;
+        // the tracer wants the registers from before and the instruction bytes in case the
+        // instruction overwrites itself
+        let traced = self.tracer.as_ref().map(|_| {
+            let bytes: Vec<u8> = (0..4)
+                .map(|offset| self.ram[(address as usize + offset) % self.ram.len()])
+                .collect();
+            (self.registers(), bytes)
+        });
         // fetch instruction
-        let operation = self.fetch();
+        let operation = self.fetch()?;
+        // only the accesses the instruction itself makes are logged, not the fetch
+        self.accesses.clear();
         // decode and execute instruction
-        self.execute(operation);
+        let result = self.execute(address, operation);
+        if result.is_err() {
+            self.program_counter = address;
+        }
+        // the faulting instruction is traced too, it's the one a crash dump needs most
+        if let Some((before, bytes)) = traced {
+            let after = self.registers();
+            let variant = self.config.variant;
+            if let Some(tracer) = &mut self.tracer {
+                tracer.record(address, &bytes, variant, &before, &after, result.err());
+            }
+        }
+        result?;
+        if let Some(profiler) = &mut self.profiler {
+            profiler.record(
+                address,
+                operation,
+                self.program_counter,
+                stack_before,
+                self.stack_pointer,
+            );
+        }
+        // XO-CHIP's F000 NNNN is 4 bytes long
+        let size = if self.xo_chip() && operation == 0xf000 {
+            4
+        } else {
+            2
+        };
+        if let Some(coverage) = &mut self.coverage {
+            coverage.record(address, size, &self.accesses);
+        }
+        if let Some(heatmap) = &mut self.heatmap {
+            heatmap.record(address, size, &self.accesses);
+        }
+        Ok(StepInfo {
+            address,
+            opcode: operation,
+        })
     }
 
-    fn fetch(&mut self) -> u16 {
+    fn fetch(&mut self) -> Result<u16, EmuError> {
         // fetches the opcode ( all Chip-8 opcodes are exactly 2 bytes)
-        let upper_byte = self.ram[self.program_counter as usize] as u16;
-        let lower_byte: u16 = self.ram[(self.program_counter + 1) as usize] as u16;
+        let address = self.program_counter;
+        let upper_byte = self.read_ram(address, address as usize)? as u16;
+        let lower_byte = self.read_ram(address, address as usize + 1)? as u16;
         let op = (upper_byte << 8) | lower_byte;
-        self.program_counter += 2;
-        op
+        self.advance(2);
+        Ok(op)
+    }
+
+    /// Moves the program counter forward, wrapping around RAM if the memory policy says so.
+    fn advance(&mut self, bytes: u16) {
+        self.program_counter = self.program_counter.wrapping_add(bytes);
+        if self.config.memory_policy == MemoryPolicy::Wrap {
+            self.program_counter = (self.program_counter as usize % self.ram.len()) as u16;
+        }
+    }
+
+    /// Skips the next instruction, stepping over both halves of XO-CHIP's 4 byte F000 NNNN.
+    fn skip(&mut self, address: u16) -> Result<(), EmuError> {
+        let next = self.program_counter as usize;
+        let long_load = self.xo_chip()
+            && self.read_ram(address, next)? == 0xf0
+            && self.read_ram(address, next + 1)? == 0x00;
+        self.advance(if long_load { 4 } else { 2 });
+        Ok(())
+    }
+
+    /// RAM the last instruction run by `tick` read and wrote, in order.
+    pub fn last_accesses(&self) -> &[MemoryAccess] {
+        &self.accesses
+    }
+
+    /// Starts tracing every instruction `tick` runs, or stops with `None`.
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
+    }
+
+    /// Stops tracing and hands back the tracer, to `finish` it.
+    pub fn take_tracer(&mut self) -> Option<Tracer> {
+        self.tracer.take()
+    }
+
+    pub fn tracer(&self) -> Option<&Tracer> {
+        self.tracer.as_ref()
+    }
+
+    /// Starts profiling every instruction `tick` runs, or stops with `None`.
+    pub fn set_profiler(&mut self, profiler: Option<Profiler>) {
+        self.profiler = profiler;
+    }
+
+    /// Stops profiling and hands back the profiler with its results.
+    pub fn take_profiler(&mut self) -> Option<Profiler> {
+        self.profiler.take()
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    /// Starts tracking which ROM bytes run and get read, or stops with `None`.
+    pub fn set_coverage(&mut self, coverage: Option<Coverage>) {
+        self.coverage = coverage;
+    }
+
+    /// Stops tracking coverage and hands back the results.
+    pub fn take_coverage(&mut self) -> Option<Coverage> {
+        self.coverage.take()
+    }
+
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
     }
 
-    pub fn tick_timers(&mut self, buzzer: &sound::Buzzer) {
+    /// Starts lighting up a heatmap of RAM accesses, or stops with `None`.
+    pub fn set_heatmap(&mut self, heatmap: Option<Heatmap>) {
+        self.heatmap = heatmap;
+    }
+
+    pub fn take_heatmap(&mut self) -> Option<Heatmap> {
+        self.heatmap.take()
+    }
+
+    pub fn heatmap(&self) -> Option<&Heatmap> {
+        self.heatmap.as_ref()
+    }
+
+    /// Runs one 60Hz frame: `Config::ticks_per_frame` instructions and then the timers.
+    /// Stops at the first fault, without ticking the timers.
+    pub fn run_frame(&mut self) -> Result<(), EmuError> {
+        for _ in 0..self.config.ticks_per_frame {
+            self.tick()?;
+        }
+        self.tick_timers();
+        Ok(())
+    }
+
+    /// Counts the timers down, call at 60Hz. The buzzer should sound while `sound_status` is true.
+    pub fn tick_timers(&mut self) {
+        // timers tick at the display refresh, which is what DXYN waits for with the display wait quirk
+        self.vblank = true;
+        if let Some(heatmap) = &mut self.heatmap {
+            heatmap.fade();
+        }
         if self.delay_timer > 0 {
             self.delay_timer -= 1;
         }
         if self.sound_timer > 0 {
-            if self.sound_timer == 1 {
-                buzzer.set(false);
-            } else {
-                buzzer.set(true);
-            }
             self.sound_timer -= 1;
         }
     }
 
+    fn superchip(&self) -> bool {
+        self.config.variant != Variant::Chip8
+    }
+
+    fn xo_chip(&self) -> bool {
+        self.config.variant == Variant::XoChip
+    }
+
+    /// XORs a sprite `rows` tall and `bytes_per_row` bytes wide from `source` onto a plane.
+    /// Returns the number of rows that collided with a lit pixel and the number clipped off the bottom.
+    #[allow(clippy::too_many_arguments)]
+    fn draw_sprite(
+        &mut self,
+        address: u16,
+        plane: usize,
+        source: usize,
+        x_coordinate: usize,
+        y_coordinate: usize,
+        rows: usize,
+        bytes_per_row: usize,
+    ) -> Result<(u8, u8), EmuError> {
+        let width = self.display.width();
+        let height = self.display.height();
+        // the starting position always wraps, the sprite itself is wrapped or clipped
+        let x_coordinate = x_coordinate % width;
+        let y_coordinate = y_coordinate % height;
+        let clip = self.config.quirks.clip_sprites;
+        let mut collided_rows = 0;
+        let mut clipped_rows = 0;
+        for row in 0..rows {
+            if clip && y_coordinate + row >= height {
+                clipped_rows += 1;
+                continue;
+            }
+            let mut collided = false;
+            for byte in 0..bytes_per_row {
+                let target = source + row * bytes_per_row + byte;
+                let pixels = self.read_ram(address, target)?;
+                for bit in 0..8 {
+                    let col = byte * 8 + bit;
+                    if clip && x_coordinate + col >= width {
+                        break;
+                    }
+                    // fetch current pixels bit (on or off using a mask
+                    if (pixels & (0b1000_0000 >> bit)) != 0 {
+                        let x = (x_coordinate + col) % width;
+                        let y = (y_coordinate + row) % height;
+                        // flip the pixel and set
+                        collided |= self.display.flip(plane, x, y);
+                    }
+                }
+            }
+            collided_rows += collided as u8;
+        }
+        Ok((collided_rows, clipped_rows))
+    }
+
+    fn num_flags(&self) -> usize {
+        if self.xo_chip() {
+            NUM_FLAGS
+        } else {
+            SUPERCHIP_NUM_FLAGS
+        }
+    }
+
+    /// Registers from X to Y inclusive, counting down if Y is below X.
+    fn register_range(x: u16, y: u16) -> Box<dyn Iterator<Item = usize>> {
+        let (x, y) = (x as usize, y as usize);
+        if x <= y {
+            Box::new(x..=y)
+        } else {
+            Box::new((y..=x).rev())
+        }
+    }
+
+    /// The value 8XY6/8XYE shift, which depends on the shift quirk.
+    fn shift_source(&self, x: usize, y: usize) -> u8 {
+        if self.config.quirks.shift_uses_vy {
+            self.v_register[y]
+        } else {
+            self.v_register[x]
+        }
+    }
+
+    /// Moves I after FX55/FX65 stored or loaded V0 to VX.
+    fn apply_load_store_quirk(&mut self, x: usize) {
+        let increment = match self.config.quirks.load_store {
+            LoadStoreQuirk::Unchanged => return,
+            LoadStoreQuirk::IncrementByX => x,
+            LoadStoreQuirk::IncrementByXPlusOne => x + 1,
+        };
+        self.i_register = self.i_register.wrapping_add(increment as u16);
+    }
+
     // execute instructions (opcodes)
-    fn execute(&mut self, operation: u16) {
+    fn execute(&mut self, address: u16, operation: u16) -> Result<(), EmuError> {
         // separate out hex digits (bytes) of the opcode
         let first_byte = (operation & 0xF000) >> 12;
         let second_byte = (operation & 0x0F00) >> 8;
@@ -128,18 +637,55 @@ impl Emulator {
         // figure out what opcode it is
         match (first_byte, second_byte, third_byte, fourth_byte) {
             // 0000 - NOP (no op)
-            (0, 0, 0, 0) => return,
+            (0, 0, 0, 0) => {}
 
             // 00E0 - CLS (clear screen)
             (0, 0, 0xe, 0) => {
-                self.screen = [false; SCREEN_WIDTH * SCREEN_HEIGHT];
+                self.display.clear();
+            }
+
+            // 00CN - scroll the display down N pixels (SUPER-CHIP)
+            (0, 0, 0xc, _) if self.superchip() => {
+                self.display.scroll_down(fourth_byte as usize);
+            }
+
+            // 00DN - scroll the display up N pixels (XO-CHIP)
+            (0, 0, 0xd, _) if self.xo_chip() => {
+                self.display.scroll_up(fourth_byte as usize);
+            }
+
+            // 00FB - scroll the display right 4 pixels (SUPER-CHIP)
+            (0, 0, 0xf, 0xb) if self.superchip() => {
+                self.display.scroll_right(4);
+            }
+
+            // 00FC - scroll the display left 4 pixels (SUPER-CHIP)
+            (0, 0, 0xf, 0xc) if self.superchip() => {
+                self.display.scroll_left(4);
+            }
+
+            // 00FD - exit the interpreter (SUPER-CHIP)
+            // reported as an error so the program counter stays here and every tick exits again
+            (0, 0, 0xf, 0xd) if self.superchip() => {
+                return Err(EmuError::Exited { address });
+            }
+
+            // 00FE - switch to 64x32 low resolution (SUPER-CHIP)
+            (0, 0, 0xf, 0xe) if self.superchip() => {
+                self.display.set_high_res(false);
+            }
+
+            // 00FF - switch to 128x64 high resolution (SUPER-CHIP)
+            (0, 0, 0xf, 0xf) if self.superchip() => {
+                self.display.set_high_res(true);
             }
 
             // 00EE - RET (return from subroutine)
             (0, 0, 0xe, 0xe) => {
                 // move the program counter to the specified address and resume execution from there
-                let return_address = self.pop();
-                self.program_counter = return_address;
+                if let Some(return_address) = self.pop(address)? {
+                    self.program_counter = return_address;
+                }
             }
 
             // 1NNN - JMP NNN (jump)
@@ -151,7 +697,7 @@ impl Emulator {
             // 2NNN - CALL NNN (call subroutine)
             (2, _, _, _) => {
                 let call_address = operation & 0xfff; // NNN
-                self.push(self.program_counter);
+                self.push(address, self.program_counter)?;
                 self.program_counter = call_address;
             }
 
@@ -160,7 +706,7 @@ impl Emulator {
                 let x = second_byte as usize;
                 let nn = (operation & 0xff) as u8; // NN
                 if self.v_register[x] == nn {
-                    self.program_counter += 2;
+                    self.skip(address)?;
                 }
             }
 
@@ -169,7 +715,7 @@ impl Emulator {
                 let x = second_byte as usize;
                 let nn = (operation & 0xff) as u8; // nn
                 if self.v_register[x] != nn {
-                    self.program_counter += 2;
+                    self.skip(address)?;
                 }
             }
 
@@ -178,7 +724,26 @@ impl Emulator {
                 let x = second_byte as usize;
                 let y = third_byte as usize;
                 if self.v_register[x] == self.v_register[y] {
-                    self.program_counter += 2;
+                    self.skip(address)?;
+                }
+            }
+
+            // 5XY2 - save VX to VY in memory starting at I, in either order (XO-CHIP)
+            // I is left unchanged
+            (5, _, _, 2) if self.xo_chip() => {
+                let i = self.i_register as usize;
+                for (offset, register) in Self::register_range(second_byte, third_byte).enumerate()
+                {
+                    self.write_ram(address, i + offset, self.v_register[register])?;
+                }
+            }
+
+            // 5XY3 - load VX to VY from memory starting at I, in either order (XO-CHIP)
+            (5, _, _, 3) if self.xo_chip() => {
+                let i = self.i_register as usize;
+                for (offset, register) in Self::register_range(second_byte, third_byte).enumerate()
+                {
+                    self.v_register[register] = self.read_ram(address, i + offset)?;
                 }
             }
 
@@ -208,6 +773,9 @@ impl Emulator {
                 let x = second_byte as usize;
                 let y = third_byte as usize;
                 self.v_register[x] |= self.v_register[y];
+                if self.config.quirks.logic_resets_vf {
+                    self.v_register[0xf] = 0;
+                }
             }
 
             // 8XY2 - VX &= VY (Set VX to VX and VY)
@@ -215,6 +783,9 @@ impl Emulator {
                 let x = second_byte as usize;
                 let y = third_byte as usize;
                 self.v_register[x] &= self.v_register[y];
+                if self.config.quirks.logic_resets_vf {
+                    self.v_register[0xf] = 0;
+                }
             }
 
             // 8XY3 - VX ^= VY (Set VX to VX xor VY)
@@ -222,6 +793,9 @@ impl Emulator {
                 let x = second_byte as usize;
                 let y = third_byte as usize;
                 self.v_register[x] ^= self.v_register[y];
+                if self.config.quirks.logic_resets_vf {
+                    self.v_register[0xf] = 0;
+                }
             }
 
             // 8XY4 - VX += VY (Add with carry, Set VX to VX + VY. Set VF = carry)
@@ -245,11 +819,12 @@ impl Emulator {
             }
 
             // 8XY6 - VX >>= 1 (Right shift, Set VX to VX >> 1)
-            // stores the dropped bit in VF
+            // stores the dropped bit in VF, with the shift quirk VX = VY >> 1
             (8, _, _, 6) => {
                 let x = second_byte as usize;
-                let least_significant_bit = self.v_register[x] & 1;
-                self.v_register[x] >>= 1; // shift the least significant bit to the right
+                let source = self.shift_source(x, third_byte as usize);
+                let least_significant_bit = source & 1;
+                self.v_register[x] = source >> 1; // shift the least significant bit to the right
                 self.v_register[0xf] = least_significant_bit;
             }
 
@@ -268,8 +843,9 @@ impl Emulator {
             // same as 8XY6 but shifting VX to the left (storing overflow in VF)
             (8, _, _, 0xe) => {
                 let x = second_byte as usize;
-                let most_significant_bit = (self.v_register[x] >> 7) & 1;
-                self.v_register[x] <<= 1; // shift the most significant bit to the left
+                let source = self.shift_source(x, third_byte as usize);
+                let most_significant_bit = (source >> 7) & 1;
+                self.v_register[x] = source << 1; // shift the most significant bit to the left
                 self.v_register[0xf] = most_significant_bit;
             }
 
@@ -279,7 +855,7 @@ impl Emulator {
                 let x = second_byte as usize;
                 let y = third_byte as usize;
                 if self.v_register[x] != self.v_register[y] {
-                    self.program_counter += 2;
+                    self.skip(address)?;
                 }
             }
 
@@ -291,11 +867,17 @@ impl Emulator {
             }
 
             // BNNN - PC = V0 + NNN
-            // jump to NNN + V0 (always uses V0)
+            // jump to NNN + V0
             // moves the PC to the sum of the value stored in V0 and the raw value 0xNNN supplied in the opcode
+            // with the jump quirk this is BXNN, which adds VX instead of V0
             (0xb, _, _, _) => {
                 let nnn = operation & 0xfff; // NNN
-                self.program_counter = self.v_register[0] as u16 + nnn;
+                let register = if self.config.quirks.jump_uses_vx {
+                    second_byte as usize
+                } else {
+                    0
+                };
+                self.program_counter = self.v_register[register] as u16 + nnn;
             }
 
             // CXNN - VX = random byte AND NN
@@ -303,46 +885,62 @@ impl Emulator {
             (0xc, _, _, _) => {
                 let x = second_byte as usize;
                 let nn = (operation & 0xff) as u8; // NN
-                let random_byte: u8 = random();
+                let random_byte = self.rng.next_byte();
                 self.v_register[x] = random_byte & nn;
             }
 
             //DXYN - draw sprite, at coordinates VX, VY, N bytes tall
+            // DXY0 draws a 16x16 sprite on SUPER-CHIP
             (0xd, _, _, _) => {
-                let x_coordinate = self.v_register[second_byte as usize] as u16;
-                let y_coordinate = self.v_register[third_byte as usize] as u16;
-                let number_of_rows = fourth_byte;
-                let mut flipped = false;
-                for row in 0..number_of_rows {
-                    let address = self.i_register + row as u16;
-                    let pixels = self.ram[address as usize];
-                    for col in 0..8 {
-                        if (pixels & (0b1000_0000 >> col)) != 0 {
-                            // fetch current pixels bit (on or off using a mask
-                            let x = (x_coordinate + col) as usize % SCREEN_WIDTH;
-                            let y = (y_coordinate + row) as usize % SCREEN_HEIGHT;
-                            // get the index of the current pixel
-                            let index = x + y * SCREEN_WIDTH;
-                            // flip the pixel and set
-                            flipped |= self.screen[index];
-                            self.screen[index] ^= true;
-                        }
+                // the SUPER-CHIP interpreter only waited for the display interrupt in low resolution
+                if self.config.quirks.display_wait && !self.display.is_high_res() {
+                    if !self.vblank {
+                        // keep retrying the draw until the next frame starts
+                        self.program_counter = address;
+                        return Ok(());
                     }
+                    self.vblank = false;
                 }
-                if flipped {
-                    self.v_register[0xf] = 1;
+                let x_coordinate = self.v_register[second_byte as usize] as usize;
+                let y_coordinate = self.v_register[third_byte as usize] as usize;
+                let (rows, bytes_per_row) = if fourth_byte == 0 && self.superchip() {
+                    (16, 2)
                 } else {
-                    self.v_register[0xf] = 0;
+                    (fourth_byte as usize, 1)
+                };
+                // with XO-CHIP bitplanes each selected plane takes the next sprite's worth of data
+                let sprite_size = rows * bytes_per_row;
+                let planes: Vec<usize> = self.display.selected_plane_indices().collect();
+                let (mut collided, mut clipped) = (0, 0);
+                for (index, plane) in planes.into_iter().enumerate() {
+                    let source = self.i_register as usize + index * sprite_size;
+                    let (plane_collided, plane_clipped) = self.draw_sprite(
+                        address,
+                        plane,
+                        source,
+                        x_coordinate,
+                        y_coordinate,
+                        rows,
+                        bytes_per_row,
+                    )?;
+                    collided = collided.max(plane_collided);
+                    clipped = clipped.max(plane_clipped);
                 }
+                // SUPER-CHIP reports the number of rows that collided or were clipped in high resolution
+                self.v_register[0xf] = if self.superchip() && self.display.is_high_res() {
+                    collided + clipped
+                } else {
+                    (collided > 0) as u8
+                };
             }
 
             // EX9E - Skip if key pressed
             (0xe, _, 9, 0xe) => {
                 let x = second_byte as usize;
                 let vx = self.v_register[x];
-                let key = self.keys[vx as usize];
+                let key = self.key(vx as usize)?;
                 if key {
-                    self.program_counter += 2;
+                    self.skip(address)?;
                 }
             }
 
@@ -350,10 +948,35 @@ impl Emulator {
             (0xe, _, 0xa, 1) => {
                 let x = second_byte as usize;
                 let vx = self.v_register[x];
-                let key = self.keys[vx as usize];
+                let key = self.key(vx as usize)?;
                 if !key {
-                    self.program_counter += 2;
+                    self.skip(address)?;
+                }
+            }
+
+            // F000 NNNN - I = NNNN (XO-CHIP)
+            // the address is the 16 bit word following the instruction
+            (0xf, 0, 0, 0) if self.xo_chip() => {
+                let next = self.program_counter as usize;
+                let upper_byte = self.read_ram(address, next)? as u16;
+                let lower_byte = self.read_ram(address, next + 1)? as u16;
+                self.i_register = (upper_byte << 8) | lower_byte;
+                self.advance(2);
+            }
+
+            // FN01 - select the bitplanes N to draw to (XO-CHIP)
+            (0xf, _, 0, 1) if self.xo_chip() => {
+                self.display.select_planes(second_byte as u8);
+            }
+
+            // F002 - load the 16 byte audio pattern from I (XO-CHIP)
+            (0xf, 0, 0, 2) if self.xo_chip() => {
+                let i = self.i_register as usize;
+                let mut pattern = [0; AUDIO_PATTERN_SIZE];
+                for (offset, byte) in pattern.iter_mut().enumerate() {
+                    *byte = self.read_ram(address, i + offset)?;
                 }
+                self.audio_pattern = Some(pattern);
             }
 
             // FX07 - VX = delay timer
@@ -377,8 +1000,8 @@ impl Emulator {
                     }
                 }
                 if !pressed {
-                    // jump back (this causes the endless loop to block the program)
-                    self.program_counter -= 2;
+                    // stay on this instruction (this causes the endless loop to block the program)
+                    self.program_counter = address;
                 }
             }
 
@@ -412,6 +1035,20 @@ impl Emulator {
                 self.i_register = self.v_register[x] as u16 * 5;
             }
 
+            // FX30 - Set I to big font address (SUPER-CHIP)
+            // sets I to the location of the 8x10 sprite for the digit in VX
+            (0xf, _, 3, 0) if self.superchip() => {
+                let x = second_byte as usize;
+                let digit = (self.v_register[x] & 0xf) as usize;
+                self.i_register = (BIG_FONTSET_ADDR + digit * 10) as u16;
+            }
+
+            // FX3A - pitch = VX (XO-CHIP)
+            // sets the playback rate of the audio pattern
+            (0xf, _, 3, 0xa) if self.xo_chip() => {
+                self.pitch = self.v_register[second_byte as usize];
+            }
+
             // FX33 - I = BCD of VX
             // stores the binary-coded decimal representation of VX, with the most significant digit in I
             (0xf, _, 3, 3) => {
@@ -425,9 +1062,10 @@ impl Emulator {
                 let ones = (vx % 10.0).floor() as u8;
 
                 // store the BCD into RAM, beginning at the address currently in the I Register and moving alon
-                self.ram[self.i_register as usize] = hundreds;
-                self.ram[(self.i_register + 1) as usize] = tens;
-                self.ram[(self.i_register + 2) as usize] = ones;
+                let i = self.i_register as usize;
+                self.write_ram(address, i, hundreds)?;
+                self.write_ram(address, i + 1, tens)?;
+                self.write_ram(address, i + 2, ones)?;
             }
 
             // FX55 - Store V0 to VX in memory starting at I
@@ -435,8 +1073,9 @@ impl Emulator {
                 let x = second_byte as usize;
                 let i = self.i_register as usize;
                 for index in 0..=x {
-                    self.ram[i + index] = self.v_register[index];
+                    self.write_ram(address, i + index, self.v_register[index])?;
                 }
+                self.apply_load_store_quirk(x);
             }
 
             // FX65 - Load I into V0 to VX from memory
@@ -444,32 +1083,262 @@ impl Emulator {
                 let x = second_byte as usize;
                 let i = self.i_register as usize;
                 for index in 0..=x {
-                    self.v_register[index] = self.ram[i + index];
+                    self.v_register[index] = self.read_ram(address, i + index)?;
                 }
+                self.apply_load_store_quirk(x);
+            }
+
+            // FX75 - Store V0 to VX in the RPL user flags (SUPER-CHIP)
+            (0xf, _, 7, 5) if self.superchip() => {
+                let x = (second_byte as usize).min(self.num_flags() - 1);
+                self.flags[..=x].copy_from_slice(&self.v_register[..=x]);
             }
 
-            (_, _, _, _) => unimplemented!("Opcode not yet implemented: {}", operation),
+            // FX85 - Load V0 to VX from the RPL user flags (SUPER-CHIP)
+            (0xf, _, 8, 5) if self.superchip() => {
+                let x = (second_byte as usize).min(self.num_flags() - 1);
+                self.v_register[..=x].copy_from_slice(&self.flags[..=x]);
+            }
+
+            (_, _, _, _) => {
+                return Err(EmuError::UnknownOpcode {
+                    address,
+                    opcode: operation,
+                })
+            }
         }
+        Ok(())
     }
 
     pub fn get_display(&self) -> &[bool] {
         //  passes a pointer to then screen buffer up to the frontend
-        &self.screen
+        // the buffer is display_width() * display_height() pixels, row by row
+        self.display.plane(0)
     }
 
-    pub fn keypress(&mut self, index: usize, pressed: bool) {
+    /// Pixels of one XO-CHIP bitplane, laid out like `get_display`.
+    /// The color of a pixel is `plane 0 | plane 1 << 1`, giving four colors.
+    pub fn get_plane(&self, plane: usize) -> &[bool] {
+        self.display.plane(plane)
+    }
+
+    /// The color of every pixel as an index into `PALETTE`, laid out like `get_display`.
+    pub fn pixel_colors(&self) -> Vec<u8> {
+        self.display
+            .plane(0)
+            .iter()
+            .zip(self.display.plane(1))
+            .map(|(&low, &high)| low as u8 | (high as u8) << 1)
+            .collect()
+    }
+
+    /// Bitmask of the planes the program is drawing to (XO-CHIP FN01).
+    pub fn selected_planes(&self) -> u8 {
+        self.display.selected_planes()
+    }
+
+    /// Width of the display in its current resolution
+    pub fn display_width(&self) -> usize {
+        self.display.width()
+    }
+
+    /// Height of the display in its current resolution
+    pub fn display_height(&self) -> usize {
+        self.display.height()
+    }
+
+    pub fn is_high_res(&self) -> bool {
+        self.display.is_high_res()
+    }
+
+    /// The whole keypad as a bitmask, bit N set while key N is pressed.
+    pub fn pressed_keys(&self) -> u16 {
+        self.keys
+            .iter()
+            .enumerate()
+            .fold(0, |keys, (key, &pressed)| keys | ((pressed as u16) << key))
+    }
+
+    /// Sets the whole keypad from a bitmask, see `pressed_keys`.
+    pub fn set_pressed_keys(&mut self, keys: u16) {
+        for (key, pressed) in self.keys.iter_mut().enumerate() {
+            *pressed = keys & (1 << key) != 0;
+        }
+    }
+
+    pub fn is_key_pressed(&self, index: usize) -> Result<bool, EmuError> {
+        self.keys
+            .get(index)
+            .copied()
+            .ok_or(EmuError::InvalidKey { key: index })
+    }
+
+    pub fn keypress(&mut self, index: usize, pressed: bool) -> Result<(), EmuError> {
         // sets key as pressed or not
-        self.keys[index] = pressed;
+        let key = self
+            .keys
+            .get_mut(index)
+            .ok_or(EmuError::InvalidKey { key: index })?;
+        *key = pressed;
+        Ok(())
     }
 
-    pub fn load(&mut self, data: &[u8]) {
-        // loads data into the RAM
+    pub fn load(&mut self, data: &[u8]) -> Result<(), EmuError> {
+        // loads data into the RAM, programs have everything from 0x200 to the end of RAM
         let start = START_ADDR as usize;
-        let end = (START_ADDR as usize) + data.len();
+        let max = self.ram.len() - start;
+        if data.len() > max {
+            return Err(EmuError::RomTooLarge {
+                size: data.len(),
+                max,
+            });
+        }
+        let end = start + data.len();
         self.ram[start..end].copy_from_slice(data);
+        Ok(())
+    }
+
+    pub fn registers(&self) -> Registers {
+        Registers {
+            program_counter: self.program_counter,
+            i: self.i_register,
+            v: self.v_register,
+            stack_pointer: self.stack_pointer,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+        }
+    }
+
+    /// Overwrites the registers and timers, for debuggers and tools. The stack pointer is left
+    /// alone since moving it would expose stale or missing return addresses. Fails, changing
+    /// nothing, if the program counter is outside of RAM.
+    pub fn set_registers(&mut self, registers: &Registers) -> Result<(), EmuError> {
+        self.check_address(registers.program_counter as usize)?;
+        self.program_counter = registers.program_counter;
+        self.i_register = registers.i;
+        self.v_register = registers.v;
+        self.delay_timer = registers.delay_timer;
+        self.sound_timer = registers.sound_timer;
+        Ok(())
+    }
+
+    fn check_address(&self, target: usize) -> Result<(), EmuError> {
+        if target < self.ram.len() {
+            Ok(())
+        } else {
+            Err(EmuError::AddressOutOfRange { target })
+        }
+    }
+
+    /// Sets register V`index`, 0 to F.
+    pub fn set_v(&mut self, index: usize, value: u8) -> Result<(), EmuError> {
+        let register = self
+            .v_register
+            .get_mut(index)
+            .ok_or(EmuError::InvalidRegister { index })?;
+        *register = value;
+        Ok(())
+    }
+
+    /// Moves the program counter, which has to stay inside RAM.
+    pub fn set_program_counter(&mut self, address: u16) -> Result<(), EmuError> {
+        self.check_address(address as usize)?;
+        self.program_counter = address;
+        Ok(())
+    }
+
+    /// Sets I. It can point anywhere, instructions using it check the address themselves.
+    pub fn set_i(&mut self, value: u16) {
+        self.i_register = value;
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn set_delay_timer(&mut self, value: u8) {
+        self.delay_timer = value;
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    pub fn set_sound_timer(&mut self, value: u8) {
+        self.sound_timer = value;
+    }
+
+    /// All of RAM, 4K or 64K with XO-CHIP.
+    pub fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    /// The return addresses on the stack, outermost first. Slots that can't be read, like a RAM
+    /// stack that ran off the bottom of memory, show as 0.
+    pub fn call_stack(&self) -> Vec<u16> {
+        (0..self.stack_pointer)
+            .map(|slot| {
+                if self.config.stack.in_ram {
+                    Self::stack_slot_address(0, slot)
+                        .ok()
+                        .and_then(|target| self.ram.get(target..target + 2))
+                        .map_or(0, |bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+                } else {
+                    self.stack.get(slot).copied().unwrap_or(0)
+                }
+            })
+            .collect()
+    }
+
+    /// `length` bytes of RAM from `address`, `None` if they run past the end of RAM.
+    pub fn read_memory(&self, address: usize, length: usize) -> Option<&[u8]> {
+        self.ram.get(address..address.checked_add(length)?)
+    }
+
+    /// Copies `data` into RAM at `address`, for debuggers and tools. Fails, changing nothing, if
+    /// it doesn't fit.
+    pub fn write_memory(&mut self, address: usize, data: &[u8]) -> Result<(), EmuError> {
+        let end = address.saturating_add(data.len());
+        self.check_address(address.max(end.saturating_sub(1)))?;
+        self.ram[address..end].copy_from_slice(data);
+        Ok(())
     }
 
     pub fn sound_status(&self) -> bool {
         self.sound_timer > 0
     }
+
+    /// The XO-CHIP audio pattern, `None` until a program loads one with F002.
+    pub fn audio_pattern(&self) -> Option<&[u8; AUDIO_PATTERN_SIZE]> {
+        self.audio_pattern.as_ref()
+    }
+
+    /// The XO-CHIP pitch register, see `audio_pattern_rate`.
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_depth_stack_overflows_instead_of_wrapping() {
+        let mut emulator = Emulator::with_config(Config {
+            stack: StackConfig {
+                depth: Some(0),
+                overflow: StackOverflowBehavior::Wrap,
+                in_ram: false,
+            },
+            ..Config::default()
+        });
+        // 2200 calls itself
+        emulator.load(&[0x22, 0x00]).unwrap();
+        assert_eq!(
+            emulator.tick().unwrap_err(),
+            EmuError::StackOverflow { address: 0x200 }
+        );
+        assert!(emulator.call_stack().is_empty());
+    }
 }
@@ -1,39 +1,224 @@
+use chip_eight_emu::audio::AudioRenderer;
+use chip_eight_emu::coverage::Coverage;
+use chip_eight_emu::gdb::GdbServer;
+use chip_eight_emu::heatmap::{Heatmap, HEATMAP_SIZE};
+use chip_eight_emu::hud::{self, HUD_WIDTH};
+use chip_eight_emu::movie::{Movie, MoviePlayer, MovieRecorder};
+use chip_eight_emu::profile::Profiler;
+use chip_eight_emu::rewind::RewindBuffer;
+use chip_eight_emu::video::GifRecorder;
 use chip_eight_emu::*;
-use sdl2::event::Event;
+use sdl2::event::{Event, WindowEvent};
 use sdl2::keyboard::Keycode;
 use sdl2::pixels::Color;
 use sdl2::rect::Rect;
 use sdl2::render::Canvas;
 use sdl2::video::Window;
+use sdl2::VideoSubsystem;
 use std::env;
-use std::fs::File;
-use std::io::Read;
+use std::fs::{self, File};
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+
+// pixels are SCALE wide in high resolution and twice that in low resolution
+const SCALE: u32 = 8;
+const WINDOW_WIDTH: u32 = HIRES_SCREEN_WIDTH as u32 * SCALE;
+const WINDOW_HEIGHT: u32 = HIRES_SCREEN_HEIGHT as u32 * SCALE;
+const FRAMES_PER_SECOND: usize = 60;
+const DEFAULT_REWIND_SECONDS: usize = 10;
+// screenshots and GIF recordings are scaled up by this
+const DEFAULT_CAPTURE_SCALE: usize = 1;
+// pixels per cell of the heatmap window (F8) and of saved heatmaps (F7)
+const HEATMAP_SCALE: u32 = 6;
+const USAGE: &str = "<file> [--quirks <preset>] [--variant <chip8|schip|xo-chip>] [--seed <n>] \
+                     [--rewind-seconds <n>] [--capture-scale <n>] [--wav <file>] [--gdb <port>] [--coverage <file>] \
+                     [--record <movie> | --play <movie>]";
+const WINDOW_TITLE: &str = "Chip-8 Emulator";
+// drawn in the top right corner while a movie is recording or playing
+const RECORDING_COLOR: Color = Color::RGB(0xff, 0x00, 0x00);
+const PLAYBACK_COLOR: Color = Color::RGB(0x00, 0x80, 0xff);
+
+/// Command line options, everything apart from the ROM path is optional.
+struct Options {
+    rom_path: String,
+    config: Config,
+    rewind_seconds: usize,
+    capture_scale: usize,
+    wav_path: Option<String>,
+    record_path: Option<String>,
+    play_path: Option<String>,
+    gdb_port: Option<u16>,
+    coverage_path: Option<String>,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut rom_path = None;
+    let mut config = Config::default();
+    let mut rewind_seconds = DEFAULT_REWIND_SECONDS;
+    let mut capture_scale = DEFAULT_CAPTURE_SCALE;
+    let mut wav_path = None;
+    let mut record_path = None;
+    let mut play_path = None;
+    let mut gdb_port = None;
+    let mut coverage_path = None;
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--quirks" => {
+                let name = args.next().ok_or("--quirks needs a preset")?;
+                config.quirks = Quirks::from_name(name).ok_or(format!(
+                    "Unknown quirks preset: {} (presets: vip, chip48, schip1.1, schip, xo-chip, default)",
+                    name
+                ))?;
+            }
+            "--variant" => {
+                let name = args.next().ok_or("--variant needs a name")?;
+                config.variant =
+                    Variant::from_name(name).ok_or(format!("Unknown variant: {}", name))?;
+            }
+            "--seed" => {
+                let seed = args.next().ok_or("--seed needs a number")?;
+                config.seed = Some(parse_number(seed).ok_or(format!("Invalid seed: {}", seed))?);
+            }
+            "--rewind-seconds" => {
+                let seconds = args.next().ok_or("--rewind-seconds needs a number")?;
+                rewind_seconds = seconds
+                    .parse()
+                    .map_err(|_| format!("Invalid number of seconds: {}", seconds))?;
+            }
+            "--capture-scale" => {
+                let scale = args.next().ok_or("--capture-scale needs a number")?;
+                capture_scale = scale
+                    .parse()
+                    .ok()
+                    .filter(|&scale| scale > 0)
+                    .ok_or(format!("Invalid capture scale: {}", scale))?;
+            }
+            "--wav" => {
+                wav_path = Some(args.next().ok_or("--wav needs a file")?.clone());
+            }
+            "--record" => {
+                record_path = Some(args.next().ok_or("--record needs a file")?.clone());
+            }
+            "--play" => {
+                play_path = Some(args.next().ok_or("--play needs a file")?.clone());
+            }
+            "--gdb" => {
+                let port = args.next().ok_or("--gdb needs a port")?;
+                gdb_port = Some(
+                    port.parse()
+                        .map_err(|_| format!("Invalid port: {}", port))?,
+                );
+            }
+            "--coverage" => {
+                coverage_path = Some(args.next().ok_or("--coverage needs a file")?.clone());
+            }
+            _ if rom_path.is_none() && !arg.starts_with("--") => rom_path = Some(arg.clone()),
+            _ => return Err(format!("Unexpected argument: {}", arg)),
+        }
+    }
+    let rom_path = rom_path.ok_or("No ROM file given")?;
+    if record_path.is_some() && play_path.is_some() {
+        return Err("Can't record and play a movie at the same time".to_string());
+    }
+    // frames GDB holds up would still be recorded or played back
+    if gdb_port.is_some() && (record_path.is_some() || play_path.is_some()) {
+        return Err("Can't use --gdb with a movie".to_string());
+    }
+    Ok(Options {
+        rom_path,
+        config,
+        rewind_seconds,
+        capture_scale,
+        wav_path,
+        record_path,
+        play_path,
+        gdb_port,
+        coverage_path,
+    })
+}
 
-const SCALE: u32 = 15;
-const WINDOW_WIDTH: u32 = SCREEN_WIDTH as u32 * SCALE;
-const WINDOW_HEIGHT: u32 = SCREEN_HEIGHT as u32 * SCALE;
-const TICKS_PER_FRAME: usize = 10;
 fn main() {
     // parse command line arguments
     let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        println!("Usage: {} <file>", args[0]);
-        return;
-    }
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(message) => {
+            println!("{}", message);
+            println!("Usage: {} {}", args[0], USAGE);
+            return;
+        }
+    };
 
     // Load the ROM
-    let mut file = File::open(&args[1]).expect("Unable to open file, make sure it exists");
+    let mut file = File::open(&options.rom_path).expect("Unable to open file, make sure it exists");
     let mut buffer = Vec::new();
     file.read_to_end(&mut buffer).unwrap();
     let rom = buffer;
-    let mut chip_eight = Emulator::new();
-    chip_eight.load(&rom);
+    let mut chip_eight = Emulator::with_config(options.config);
+    if let Err(error) = chip_eight.load(&rom) {
+        println!("Unable to load {}: {}", options.rom_path, error);
+        return;
+    }
+
+    // a movie brings its own settings, so playback replaces the emulator
+    let mut player = None;
+    if let Some(path) = &options.play_path {
+        let movie = fs::read(path)
+            .map_err(|error| error.to_string())
+            .and_then(|data| Movie::from_bytes(&data).map_err(|error| error.to_string()))
+            .and_then(|movie| {
+                let emulator = movie
+                    .create_emulator(&rom)
+                    .map_err(|error| error.to_string())?;
+                Ok((movie, emulator))
+            });
+        match movie {
+            Ok((movie, emulator)) => {
+                println!("Playing {} ({} frames)", path, movie.frames.len());
+                chip_eight = emulator;
+                player = Some(MoviePlayer::new(movie));
+            }
+            Err(error) => {
+                println!("Unable to play {}: {}", path, error);
+                return;
+            }
+        }
+    }
+    // coverage covers the whole session, written out on quit
+    if options.coverage_path.is_some() {
+        let variant = chip_eight.config().variant;
+        chip_eight.set_coverage(Some(Coverage::new(&rom, variant)));
+    }
+    let mut recorder = options
+        .record_path
+        .as_ref()
+        .map(|_| MovieRecorder::new(&rom, &chip_eight));
+
+    // GDB can attach at any time, until then the game runs as usual
+    let mut gdb = match options.gdb_port {
+        Some(port) => match GdbServer::bind(("127.0.0.1", port), &chip_eight) {
+            Ok(server) => {
+                println!("Listening for GDB on port {}", port);
+                Some(server)
+            }
+            Err(error) => {
+                println!("Unable to listen for GDB on port {}: {}", port, error);
+                return;
+            }
+        },
+        None => None,
+    };
 
     // Create an SDL2 window
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
     let window = video_subsystem
-        .window("Chip-8 Emulator", WINDOW_WIDTH, WINDOW_HEIGHT)
+        .window(
+            &window_title(recorder.is_some(), player.is_some()),
+            WINDOW_WIDTH,
+            WINDOW_HEIGHT,
+        )
         .position_centered()
         .opengl()
         .build()
@@ -43,11 +228,33 @@ fn main() {
     canvas.present();
 
     let audio_context = sdl_context.audio().unwrap();
-    let buzzer = chip_eight_emu::sound::Buzzer::new(&audio_context);
+    let mut buzzer = chip_eight_emu::sound::Buzzer::new(&audio_context);
 
     // Run the emulator
     let mut event_pump = sdl_context.event_pump().unwrap();
 
+    // quick save slot, kept next to the ROM
+    let state_path = format!("{}.state", options.rom_path);
+
+    // a snapshot every frame, so holding backspace rewinds at the speed the game was played
+    let mut rewind = RewindBuffer::new(options.rewind_seconds * FRAMES_PER_SECOND, 1);
+    let mut rewinding = false;
+
+    // set once the emulator faults, after which the last frame stays on screen
+    let mut fault: Option<EmuError> = None;
+
+    // everything the buzzer plays, saved as a WAV on quit
+    let mut audio = options.wav_path.as_ref().map(|_| AudioRenderer::new());
+
+    // GIF being recorded (F11) and where it's going
+    let mut gif: Option<(String, GifRecorder<BufWriter<File>>)> = None;
+
+    // the debug panel next to the game (F1)
+    let mut show_hud = false;
+
+    // the heatmap window (F8), the emulator only keeps a heatmap while it's open
+    let mut heatmap_canvas: Option<Canvas<Window>> = None;
+
     // Game loop
     'gameloop: loop {
         for event in event_pump.poll_iter() {
@@ -59,56 +266,458 @@ fn main() {
                 } => {
                     break 'gameloop;
                 }
+                // with a second window open closing one doesn't quit, so tell them apart
+                Event::Window {
+                    window_id,
+                    win_event: WindowEvent::Close,
+                    ..
+                } => {
+                    let is_heatmap = heatmap_canvas
+                        .as_ref()
+                        .is_some_and(|canvas| canvas.window().id() == window_id);
+                    if !is_heatmap {
+                        break 'gameloop;
+                    }
+                    heatmap_canvas = None;
+                    chip_eight.set_heatmap(None);
+                }
+                // rewinding or loading a state would make a movie impossible to replay
+                Event::KeyDown {
+                    keycode: Some(Keycode::Backspace | Keycode::F9),
+                    repeat: false,
+                    ..
+                } if recorder.is_some() || player.is_some() => {
+                    println!("Rewinding and loading states are disabled during movies");
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => rewinding = true,
+                Event::KeyUp {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => rewinding = false,
+                Event::KeyDown {
+                    keycode: Some(Keycode::F5),
+                    repeat: false,
+                    ..
+                } => quick_save(&chip_eight, &state_path),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F9),
+                    repeat: false,
+                    ..
+                } => {
+                    let loaded = quick_load(&mut chip_eight, &state_path);
+                    if loaded && fault.take().is_some() {
+                        // loading a state recovers from a fault, so put the title back
+                        let _ = canvas.window_mut().set_title(WINDOW_TITLE);
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F12),
+                    repeat: false,
+                    ..
+                } => take_screenshot(&chip_eight, &options.rom_path, options.capture_scale),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F11),
+                    repeat: false,
+                    ..
+                } => {
+                    gif = match gif.take() {
+                        Some(recording) => {
+                            finish_gif(recording);
+                            None
+                        }
+                        None => start_gif(&options.rom_path, options.capture_scale),
+                    };
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F10),
+                    repeat: false,
+                    ..
+                } => match chip_eight.take_profiler() {
+                    Some(profiler) => save_profile(&profiler, &options.rom_path),
+                    None => {
+                        println!("Profiling, press F10 again to stop");
+                        chip_eight.set_profiler(Some(Profiler::new(chip_eight.config().variant)));
+                    }
+                },
+                Event::KeyDown {
+                    keycode: Some(Keycode::F1),
+                    repeat: false,
+                    ..
+                } => {
+                    show_hud = !show_hud;
+                    let width = WINDOW_WIDTH + if show_hud { HUD_WIDTH } else { 0 };
+                    if let Err(error) = canvas.window_mut().set_size(width, WINDOW_HEIGHT) {
+                        println!("Unable to resize the window: {}", error);
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F8),
+                    repeat: false,
+                    ..
+                } => {
+                    if heatmap_canvas.take().is_some() {
+                        chip_eight.set_heatmap(None);
+                    } else {
+                        heatmap_canvas = open_heatmap(&video_subsystem);
+                        if heatmap_canvas.is_some() {
+                            chip_eight.set_heatmap(Some(Heatmap::new()));
+                        }
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F7),
+                    repeat: false,
+                    ..
+                } => match chip_eight.heatmap() {
+                    Some(heatmap) => save_heatmap(heatmap, &options.rom_path),
+                    None => println!("Open the heatmap with F8 before saving it"),
+                },
                 Event::KeyDown {
                     keycode: Some(key), ..
                 } => {
                     if let Some(button) = keypress_to_button_code(key) {
-                        chip_eight.keypress(button, true);
+                        chip_eight
+                            .keypress(button, true)
+                            .expect("keypad mapping only produces keys 0-F");
                     }
                 }
                 Event::KeyUp {
                     keycode: Some(key), ..
                 } => {
                     if let Some(button) = keypress_to_button_code(key) {
-                        chip_eight.keypress(button, false);
+                        chip_eight
+                            .keypress(button, false)
+                            .expect("keypad mapping only produces keys 0-F");
                     }
                 }
                 _ => {}
             }
         }
-        for _ in 0..TICKS_PER_FRAME {
-            // 10 instructions per frame
-            chip_eight.tick(); //execute next instruction (move program counter)
+        if rewinding && recorder.is_none() && player.is_none() {
+            // step back one snapshot per frame, stopping at the oldest one
+            if rewind.rewind(&mut chip_eight) && fault.take().is_some() {
+                let _ = canvas.window_mut().set_title(WINDOW_TITLE);
+            }
+            buzzer.set(false);
+            draw_screen(&chip_eight, &mut canvas, None, show_hud);
+            continue;
+        }
+        let indicator = movie_indicator(recorder.is_some(), player.is_some());
+        if fault.is_some() {
+            draw_screen(&chip_eight, &mut canvas, indicator, show_hud);
+            continue;
         }
-        chip_eight.tick_timers(&buzzer);
 
-        draw_screen(&chip_eight, &mut canvas);
+        // movie input replaces the keyboard, and the keypad is recorded before the frame uses it
+        if let Some(movie_player) = &mut player {
+            if !movie_player.apply_frame(&mut chip_eight) {
+                finish_playback(movie_player.movie(), &chip_eight);
+                player = None;
+                let _ = canvas.window_mut().set_title(WINDOW_TITLE);
+            }
+        }
+        if let Some(recorder) = &mut recorder {
+            recorder.record_frame(&chip_eight);
+        }
+
+        // 10 instructions per frame, then the timers, unless GDB has the emulator stopped
+        let result = match &mut gdb {
+            Some(gdb) => {
+                let frame = gdb.frame();
+                gdb.run_frame(&mut chip_eight)
+                    .map(|()| gdb.frame() != frame)
+            }
+            None => chip_eight.run_frame().map(|()| true),
+        };
+        if let Err(error) = result {
+            report_fault(&error, &mut canvas, &buzzer);
+            // GDB keeps the emulator halted at the fault until a client deals with it
+            if gdb.is_none() {
+                fault = Some(error);
+            }
+        } else if result == Ok(false) {
+            buzzer.set(false);
+        } else {
+            if let Some(pattern) = chip_eight.audio_pattern() {
+                buzzer.set_pattern(pattern, chip_eight.pitch());
+            }
+            buzzer.set(chip_eight.sound_status());
+            if let Some(audio) = &mut audio {
+                audio.render_frame(&chip_eight);
+            }
+            rewind.record(&chip_eight);
+        }
+
+        draw_screen(&chip_eight, &mut canvas, indicator, show_hud);
+        if let (Some(heatmap_canvas), Some(heatmap)) = (&mut heatmap_canvas, chip_eight.heatmap()) {
+            draw_heatmap(heatmap, heatmap_canvas);
+        }
+        if let Some((path, recorder)) = &mut gif {
+            if let Err(error) = recorder.capture(&chip_eight) {
+                println!("Unable to write {}, stopped recording: {}", path, error);
+                gif = None;
+            }
+        }
+    }
+
+    if let Some(recording) = gif {
+        finish_gif(recording);
+    }
+    if let Some(profiler) = chip_eight.take_profiler() {
+        save_profile(&profiler, &options.rom_path);
+    }
+    if let (Some(coverage), Some(path)) = (chip_eight.take_coverage(), &options.coverage_path) {
+        match fs::write(path, coverage.report()) {
+            Ok(()) => println!("Saved coverage to {}", path),
+            Err(error) => println!("Unable to write coverage to {}: {}", path, error),
+        }
+    }
+    if let (Some(audio), Some(path)) = (audio, &options.wav_path) {
+        match File::create(path).and_then(|file| audio.write_wav(BufWriter::new(file))) {
+            Ok(()) => println!("Saved audio to {}", path),
+            Err(error) => println!("Unable to write audio to {}: {}", path, error),
+        }
+    }
+    if let (Some(recorder), Some(path)) = (recorder, &options.record_path) {
+        let frames = recorder.frame_count();
+        let movie = recorder.finish(&mut chip_eight);
+        match fs::write(path, movie.to_bytes()) {
+            Ok(()) => println!("Recorded {} frames to {}", frames, path),
+            Err(error) => println!("Unable to write movie to {}: {}", path, error),
+        }
+    }
+}
+
+fn window_title(recording: bool, playing: bool) -> String {
+    if recording {
+        format!("{} [REC]", WINDOW_TITLE)
+    } else if playing {
+        format!("{} [PLAY]", WINDOW_TITLE)
+    } else {
+        WINDOW_TITLE.to_string()
+    }
+}
+
+fn movie_indicator(recording: bool, playing: bool) -> Option<Color> {
+    if recording {
+        Some(RECORDING_COLOR)
+    } else if playing {
+        Some(PLAYBACK_COLOR)
+    } else {
+        None
+    }
+}
+
+/// Reports whether a finished movie ended in the state it was recorded in.
+fn finish_playback(movie: &Movie, emulator: &Emulator) {
+    match movie.verify(emulator) {
+        Ok(()) if movie.final_state_hash.is_some() => {
+            println!("Movie finished, final state matches the recording")
+        }
+        Ok(()) => println!("Movie finished"),
+        Err(error) => println!("Movie finished: {}", error),
     }
 }
 
-///  1D screen buffer array and iterate across it. If we find a white pixel (a true value), then
-///  we calculate the 2D (x, y) of the screen and draw a rectangle
-///  TODO: vsync (wait for vblank)
-fn draw_screen(emulator: &Emulator, canvas: &mut Canvas<Window>) {
+///  1D screen buffer arrays (one per bitplane) and iterate across them. If we find a lit pixel in
+///  either plane, then we calculate the 2D (x, y) of the screen and draw a rectangle in its color
+fn draw_screen(
+    emulator: &Emulator,
+    canvas: &mut Canvas<Window>,
+    indicator: Option<Color>,
+    show_hud: bool,
+) {
     // clear screen
-    canvas.set_draw_color(Color::RGB(0, 0, 0));
+    canvas.set_draw_color(palette_color(0));
     canvas.clear();
 
-    let screen_buffer = emulator.get_display();
-    canvas.set_draw_color(Color::RGB(0x0, 0xff, 0x0));
-    for (i, pixel) in screen_buffer.iter().enumerate() {
-        if *pixel {
+    // the resolution can change at runtime, so scale to whatever the emulator is showing
+    let width = emulator.display_width();
+    let scale = WINDOW_WIDTH / width as u32;
+    for (i, &color) in emulator.pixel_colors().iter().enumerate() {
+        if color != 0 {
             // convert index to x,y
-            let x = (i % SCREEN_WIDTH) as u32;
-            let y = (i / SCREEN_WIDTH) as u32;
+            let x = (i % width) as u32;
+            let y = (i / width) as u32;
             // draw pixel (scaled)
-            let rect = Rect::new((x * SCALE) as i32, (y * SCALE) as i32, SCALE, SCALE);
+            let rect = Rect::new((x * scale) as i32, (y * scale) as i32, scale, scale);
+            canvas.set_draw_color(palette_color(color));
+            canvas.fill_rect(rect).unwrap();
+        }
+    }
+    // small square in the corner showing a movie is recording or playing
+    if let Some(color) = indicator {
+        canvas.set_draw_color(color);
+        let size = SCALE * 2;
+        let rect = Rect::new((WINDOW_WIDTH - size * 2) as i32, size as i32, size, size);
+        canvas.fill_rect(rect).unwrap();
+    }
+    if show_hud {
+        hud::draw(emulator, canvas, WINDOW_WIDTH as i32);
+    }
+    canvas.present();
+}
+
+/// Opens the heatmap window (F8), without vsync since the main window already waits for it.
+fn open_heatmap(video_subsystem: &VideoSubsystem) -> Option<Canvas<Window>> {
+    let size = HEATMAP_SIZE as u32 * HEATMAP_SCALE;
+    let canvas = video_subsystem
+        .window("Heatmap", size, size)
+        .build()
+        .map_err(|error| error.to_string())
+        .and_then(|window| {
+            window
+                .into_canvas()
+                .build()
+                .map_err(|error| error.to_string())
+        });
+    match canvas {
+        Ok(canvas) => {
+            println!("Heatmap: red written, green read, blue executed, press F7 to save it");
+            Some(canvas)
+        }
+        Err(error) => {
+            println!("Unable to open the heatmap window: {}", error);
+            None
+        }
+    }
+}
+
+/// Draws a cell for every byte of RAM, 64 to a row.
+fn draw_heatmap(heatmap: &Heatmap, canvas: &mut Canvas<Window>) {
+    canvas.set_draw_color(Color::RGB(0, 0, 0));
+    canvas.clear();
+    for address in 0..HEATMAP_SIZE * HEATMAP_SIZE {
+        let [red, green, blue] = heatmap.color(address);
+        if red | green | blue != 0 {
+            let x = (address % HEATMAP_SIZE) as u32 * HEATMAP_SCALE;
+            let y = (address / HEATMAP_SIZE) as u32 * HEATMAP_SCALE;
+            let rect = Rect::new(x as i32, y as i32, HEATMAP_SCALE, HEATMAP_SCALE);
+            canvas.set_draw_color(Color::RGB(red, green, blue));
             canvas.fill_rect(rect).unwrap();
         }
     }
     canvas.present();
 }
 
+fn palette_color(color: u8) -> Color {
+    let [red, green, blue] = PALETTE[color as usize];
+    Color::RGB(red, green, blue)
+}
+
+/// A file next to the ROM named `<rom>.<n>.<extension>`, using the first free number.
+fn numbered_path(rom_path: &str, extension: &str) -> String {
+    (1..)
+        .map(|number| format!("{}.{}.{}", rom_path, number, extension))
+        .find(|path| !Path::new(path).exists())
+        .expect("some file number is free")
+}
+
+/// Saves the screen next to the ROM as a PNG (F12).
+fn take_screenshot(emulator: &Emulator, rom_path: &str, scale: usize) {
+    let path = numbered_path(rom_path, "png");
+    match screenshot::save(emulator, &path, scale) {
+        Ok(()) => println!("Saved screenshot to {}", path),
+        Err(error) => println!("Unable to save screenshot to {}: {}", path, error),
+    }
+}
+
+/// Saves the heatmap next to the ROM as a PNG (F7).
+fn save_heatmap(heatmap: &Heatmap, rom_path: &str) {
+    let path = numbered_path(rom_path, "heatmap.png");
+    match heatmap.save(&path, HEATMAP_SCALE as usize) {
+        Ok(()) => println!("Saved heatmap to {}", path),
+        Err(error) => println!("Unable to save heatmap to {}: {}", path, error),
+    }
+}
+
+/// Starts recording a GIF next to the ROM (F11).
+fn start_gif(rom_path: &str, scale: usize) -> Option<(String, GifRecorder<BufWriter<File>>)> {
+    let path = numbered_path(rom_path, "gif");
+    match File::create(&path).and_then(|file| GifRecorder::new(BufWriter::new(file), scale)) {
+        Ok(recorder) => {
+            println!("Recording GIF to {}", path);
+            Some((path, recorder))
+        }
+        Err(error) => {
+            println!("Unable to record GIF to {}: {}", path, error);
+            None
+        }
+    }
+}
+
+fn finish_gif((path, recorder): (String, GifRecorder<BufWriter<File>>)) {
+    let frames = recorder.frame_count();
+    match recorder.finish().and_then(|mut writer| writer.flush()) {
+        Ok(()) => println!("Recorded {} frames to {}", frames, path),
+        Err(error) => println!("Unable to write {}: {}", path, error),
+    }
+}
+
+/// Saves a profile report and its folded stacks next to the ROM (F10).
+fn save_profile(profiler: &Profiler, rom_path: &str) {
+    let report_path = numbered_path(rom_path, "profile.txt");
+    let folded_path = format!(
+        "{}.folded",
+        report_path
+            .strip_suffix(".txt")
+            .expect("numbered_path keeps the extension")
+    );
+    let result = fs::write(&report_path, profiler.report())
+        .and_then(|()| fs::write(&folded_path, profiler.folded_stacks()));
+    match result {
+        Ok(()) => println!(
+            "Saved a profile of {} instructions to {} and {}",
+            profiler.instructions(),
+            report_path,
+            folded_path
+        ),
+        Err(error) => println!("Unable to save the profile: {}", error),
+    }
+}
+
+/// Writes a save state of the emulator to `path` (F5).
+fn quick_save(emulator: &Emulator, path: &str) {
+    match fs::write(path, emulator.save_state()) {
+        Ok(()) => println!("Saved state to {}", path),
+        Err(error) => println!("Unable to save state to {}: {}", path, error),
+    }
+}
+
+/// Restores the save state at `path` (F9), returning whether it was loaded.
+fn quick_load(emulator: &mut Emulator, path: &str) -> bool {
+    let result = fs::read(path)
+        .map_err(|error| error.to_string())
+        .and_then(|data| {
+            emulator
+                .load_state(&data)
+                .map_err(|error| error.to_string())
+        });
+    match result {
+        Ok(()) => {
+            println!("Loaded state from {}", path);
+            true
+        }
+        Err(error) => {
+            println!("Unable to load state from {}: {}", path, error);
+            false
+        }
+    }
+}
+
+/// Stops the buzzer and shows the fault in the window title (and on stderr) so the
+/// user can see why the game stopped.
+fn report_fault(error: &EmuError, canvas: &mut Canvas<Window>, buzzer: &sound::Buzzer) {
+    eprintln!("Emulator stopped: {}", error);
+    buzzer.set(false);
+    let title = format!("{} - stopped: {} (press Esc to quit)", WINDOW_TITLE, error);
+    // the title only fails to update if it contains a nul byte
+    let _ = canvas.window_mut().set_title(&title);
+}
+
 fn keypress_to_button_code(key: Keycode) -> Option<usize> {
     // original chip 8 expects a grid of 4x4 buttons
     // 1 2 3 C
@@ -0,0 +1,255 @@
+//! Input movies: a recording of the keypad state for every frame of a session.
+//!
+//! Together with the ROM and the emulator settings (including the random seed) that's enough to
+//! replay the session exactly. A movie can also carry the hash of the final machine state, so
+//! replaying it doubles as a regression test.
+//!
+//! File layout (little endian): `"C8MV"`, version (u16), ROM hash (u64), the emulator config,
+//! frame count (u32), one u16 key bitmask per frame, then an optional final state hash.
+
+use std::fmt;
+
+use crate::state::{self, read_config, write_config, Reader, StateError, Writer};
+use crate::{Config, EmuError, Emulator};
+
+const MAGIC: &[u8; 4] = b"C8MV";
+const MOVIE_VERSION: u16 = 1;
+// config layout the version 1 movie files were written with
+const CONFIG_VERSION: u16 = 2;
+
+/// Reasons a movie can't be loaded or didn't replay as recorded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MovieError {
+    /// the movie file is malformed
+    Format(StateError),
+    /// the movie was recorded with a different ROM
+    RomMismatch { expected: u64, actual: u64 },
+    /// the emulator faulted during playback
+    Emulator(EmuError),
+    /// playback finished in a different state than the recording did
+    Desync { expected: u64, actual: u64 },
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MovieError::Format(error) => write!(f, "invalid movie: {}", error),
+            MovieError::RomMismatch { expected, actual } => write!(
+                f,
+                "movie was recorded with ROM {:016x} but this ROM is {:016x}",
+                expected, actual
+            ),
+            MovieError::Emulator(error) => write!(f, "emulator stopped during playback: {}", error),
+            MovieError::Desync { expected, actual } => write!(
+                f,
+                "playback desynced, final state is {:016x} but the recording ended at {:016x}",
+                actual, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+impl From<StateError> for MovieError {
+    fn from(error: StateError) -> Self {
+        MovieError::Format(error)
+    }
+}
+
+impl From<EmuError> for MovieError {
+    fn from(error: EmuError) -> Self {
+        MovieError::Emulator(error)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub rom_hash: u64,
+    /// settings the session ran with, the seed is always set
+    pub config: Config,
+    /// keypad bitmask (see `Emulator::pressed_keys`) for each frame
+    pub frames: Vec<u16>,
+    /// `Emulator::state_hash` after the last frame, checked on playback when present
+    pub final_state_hash: Option<u64>,
+}
+
+impl Movie {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        writer.bytes(MAGIC);
+        writer.u16(MOVIE_VERSION);
+        writer.u64(self.rom_hash);
+        write_config(&mut writer, &self.config);
+        writer.u32(self.frames.len() as u32);
+        for &keys in &self.frames {
+            writer.u16(keys);
+        }
+        match self.final_state_hash {
+            Some(hash) => {
+                writer.bool(true);
+                writer.u64(hash);
+            }
+            None => writer.bool(false),
+        }
+        writer.into_bytes()
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Movie, MovieError> {
+        let mut reader = Reader::new(data);
+        if reader
+            .bytes(MAGIC.len())
+            .map_err(|_| StateError::BadMagic)?
+            != MAGIC
+        {
+            return Err(StateError::BadMagic.into());
+        }
+        let version = reader.u16()?;
+        if version != MOVIE_VERSION {
+            return Err(StateError::UnsupportedVersion(version).into());
+        }
+        let rom_hash = reader.u64()?;
+        let config = read_config(&mut reader, CONFIG_VERSION)?;
+        if config.seed.is_none() {
+            return Err(StateError::Invalid("seed").into());
+        }
+        let frame_count = reader.u32()? as usize;
+        let frames = (0..frame_count)
+            .map(|_| reader.u16())
+            .collect::<Result<_, _>>()?;
+        let final_state_hash = if reader.bool()? {
+            Some(reader.u64()?)
+        } else {
+            None
+        };
+        if !reader.is_empty() {
+            return Err(StateError::Invalid("length").into());
+        }
+        Ok(Movie {
+            rom_hash,
+            config,
+            frames,
+            final_state_hash,
+        })
+    }
+
+    /// Creates an emulator set up exactly like the one the movie was recorded on.
+    pub fn create_emulator(&self, rom: &[u8]) -> Result<Emulator, MovieError> {
+        let actual = state::hash(rom);
+        if actual != self.rom_hash {
+            return Err(MovieError::RomMismatch {
+                expected: self.rom_hash,
+                actual,
+            });
+        }
+        let mut emulator = Emulator::with_config(self.config);
+        emulator.load(rom)?;
+        Ok(emulator)
+    }
+
+    /// Checks an emulator that has played the whole movie against the recorded final state.
+    pub fn verify(&self, emulator: &Emulator) -> Result<(), MovieError> {
+        match self.final_state_hash {
+            Some(expected) if expected != emulator.state_hash() => Err(MovieError::Desync {
+                expected,
+                actual: emulator.state_hash(),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Plays the whole movie on a fresh emulator and verifies the final state.
+    pub fn replay(&self, rom: &[u8]) -> Result<Emulator, MovieError> {
+        let mut emulator = self.create_emulator(rom)?;
+        let mut player = MoviePlayer::new(self.clone());
+        while player.apply_frame(&mut emulator) {
+            // a recording can end with the program faulting, which is fine on the last frame
+            if let Err(error) = emulator.run_frame() {
+                if !player.is_finished() {
+                    return Err(error.into());
+                }
+            }
+        }
+        self.verify(&emulator)?;
+        Ok(emulator)
+    }
+}
+
+/// Records the keypad once per frame.
+pub struct MovieRecorder {
+    movie: Movie,
+}
+
+impl MovieRecorder {
+    /// Starts recording an emulator that has just had `rom` loaded and not run yet.
+    pub fn new(rom: &[u8], emulator: &Emulator) -> Self {
+        Self {
+            movie: Movie {
+                rom_hash: state::hash(rom),
+                config: Config {
+                    seed: Some(emulator.seed()),
+                    ..*emulator.config()
+                },
+                frames: Vec::new(),
+                final_state_hash: None,
+            },
+        }
+    }
+
+    /// Call at the start of every frame, after the keypad has been updated.
+    pub fn record_frame(&mut self, emulator: &Emulator) {
+        self.movie.frames.push(emulator.pressed_keys());
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.movie.frames.len()
+    }
+
+    /// Stops recording, storing the final state of `emulator` for playback to check against.
+    /// The keypad is put back to the last recorded frame first, since input that arrived after
+    /// the last frame isn't part of the movie.
+    pub fn finish(mut self, emulator: &mut Emulator) -> Movie {
+        if let Some(&keys) = self.movie.frames.last() {
+            emulator.set_pressed_keys(keys);
+        }
+        self.movie.final_state_hash = Some(emulator.state_hash());
+        self.movie
+    }
+}
+
+/// Feeds a movie's keypad states into an emulator frame by frame.
+pub struct MoviePlayer {
+    movie: Movie,
+    frame: usize,
+}
+
+impl MoviePlayer {
+    pub fn new(movie: Movie) -> Self {
+        Self { movie, frame: 0 }
+    }
+
+    /// Call at the start of every frame instead of reading real input.
+    /// Returns false once the movie is over, leaving the keypad alone.
+    pub fn apply_frame(&mut self, emulator: &mut Emulator) -> bool {
+        match self.movie.frames.get(self.frame) {
+            Some(&keys) => {
+                emulator.set_pressed_keys(keys);
+                self.frame += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.frame >= self.movie.frames.len()
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+}
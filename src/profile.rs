@@ -0,0 +1,276 @@
+//! Profiling: how often each address and kind of instruction runs, and where the time goes by
+//! subroutine.
+//!
+//! Hand the emulator a `Profiler` with `Emulator::set_profiler` and it counts every instruction
+//! `tick` runs. Time is measured in instructions. Subroutines are followed through `2NNN` calls
+//! and `00EE` returns, kept in step with the emulator's stack pointer so quirky stacks don't
+//! confuse it. Code outside any call counts as `main`.
+//!
+//! `Profiler::report` is a plain text summary, hottest first, and `Profiler::folded_stacks` is
+//! the `main;sub_2A0;sub_31C 1234` format flamegraph tools read.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::disasm::{Op, Syntax};
+use crate::Variant;
+
+// how many lines of hot addresses the report lists
+const REPORT_ADDRESSES: usize = 50;
+
+/// What the instructions starting with each hex digit do, for the report.
+const OPCODE_CLASSES: [&str; 16] = [
+    "0___ system and screen",
+    "1NNN jump",
+    "2NNN call",
+    "3XNN skip if equal",
+    "4XNN skip if not equal",
+    "5XY_ skip if registers equal, save/load range",
+    "6XNN load",
+    "7XNN add",
+    "8XY_ arithmetic",
+    "9XY0 skip if registers not equal",
+    "ANNN load I",
+    "BNNN jump with offset",
+    "CXNN random",
+    "DXYN draw",
+    "EX__ key skips",
+    "FX__ timers, memory and misc",
+];
+
+/// Counts for one address.
+#[derive(Debug, Clone, Copy)]
+struct AddressCount {
+    opcode: u16,
+    count: u64,
+}
+
+/// Totals for one subroutine, see `Profiler::subroutines`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SubroutineProfile {
+    /// the address called
+    pub address: u16,
+    pub calls: u64,
+    /// instructions run in the subroutine itself
+    pub self_count: u64,
+    /// instructions run in the subroutine and everything it called
+    pub total_count: u64,
+}
+
+pub struct Profiler {
+    variant: Variant,
+    instructions: u64,
+    addresses: HashMap<u16, AddressCount>,
+    classes: [u64; 16],
+    calls: HashMap<u16, u64>,
+    // addresses of the subroutines being run, outermost first
+    stack: Vec<u16>,
+    // the emulator's stack pointer outside of every call seen, profiling can start mid call
+    base: Option<usize>,
+    // instructions run with each call stack
+    stacks: HashMap<Vec<u16>, u64>,
+}
+
+fn subroutine_name(address: u16) -> String {
+    format!("sub_{:03X}", address)
+}
+
+impl Profiler {
+    /// `variant` is only used to disassemble the hot addresses in the report.
+    pub fn new(variant: Variant) -> Self {
+        Self {
+            variant,
+            instructions: 0,
+            addresses: HashMap::new(),
+            classes: [0; 16],
+            calls: HashMap::new(),
+            stack: Vec::new(),
+            base: None,
+            stacks: HashMap::new(),
+        }
+    }
+
+    /// Counts an instruction `tick` ran. `program_counter` and `stack_pointer` are from after it,
+    /// `stack_before` from before.
+    pub(crate) fn record(
+        &mut self,
+        address: u16,
+        opcode: u16,
+        program_counter: u16,
+        stack_before: usize,
+        stack_pointer: usize,
+    ) {
+        self.instructions += 1;
+        self.addresses
+            .entry(address)
+            .and_modify(|entry| {
+                entry.opcode = opcode;
+                entry.count += 1;
+            })
+            .or_insert(AddressCount { opcode, count: 1 });
+        self.classes[(opcode >> 12) as usize] += 1;
+        // the call itself and the return both count towards the caller and callee respectively
+        *self.stacks.entry(self.stack.clone()).or_insert(0) += 1;
+        let mut base = *self.base.get_or_insert(stack_before);
+        if stack_pointer < base {
+            // returned from a call that was made before profiling started
+            base = stack_pointer;
+            self.base = Some(base);
+        }
+        let depth = stack_pointer - base;
+        if opcode & 0xf000 == 0x2000 && depth == self.stack.len() + 1 {
+            self.stack.push(program_counter);
+            *self.calls.entry(program_counter).or_insert(0) += 1;
+        } else if depth < self.stack.len() {
+            self.stack.truncate(depth);
+        }
+    }
+
+    /// Number of instructions counted.
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    /// Addresses and how many times they ran, most first.
+    pub fn hot_addresses(&self) -> Vec<(u16, u64)> {
+        let mut addresses: Vec<(u16, u64)> = self
+            .addresses
+            .iter()
+            .map(|(&address, entry)| (address, entry.count))
+            .collect();
+        addresses.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        addresses
+    }
+
+    /// Instructions run for each first hex digit of the opcode.
+    pub fn opcode_classes(&self) -> [u64; 16] {
+        self.classes
+    }
+
+    /// Every subroutine called, most total time first.
+    pub fn subroutines(&self) -> Vec<SubroutineProfile> {
+        let mut subroutines: HashMap<u16, SubroutineProfile> = self
+            .calls
+            .iter()
+            .map(|(&address, &calls)| {
+                let profile = SubroutineProfile {
+                    address,
+                    calls,
+                    ..SubroutineProfile::default()
+                };
+                (address, profile)
+            })
+            .collect();
+        for (stack, &count) in &self.stacks {
+            if let Some(&innermost) = stack.last() {
+                if let Some(profile) = subroutines.get_mut(&innermost) {
+                    profile.self_count += count;
+                }
+            }
+            // recursion shouldn't count the same instructions twice
+            let mut seen = Vec::new();
+            for &address in stack {
+                if !seen.contains(&address) {
+                    seen.push(address);
+                    if let Some(profile) = subroutines.get_mut(&address) {
+                        profile.total_count += count;
+                    }
+                }
+            }
+        }
+        let mut subroutines: Vec<SubroutineProfile> = subroutines.into_values().collect();
+        subroutines.sort_by(|a, b| {
+            b.total_count
+                .cmp(&a.total_count)
+                .then(a.address.cmp(&b.address))
+        });
+        subroutines
+    }
+
+    /// One `main;sub_XXX;... count` line per call stack, sorted so the output is stable.
+    pub fn folded_stacks(&self) -> String {
+        let mut lines: Vec<String> = self
+            .stacks
+            .iter()
+            .map(|(stack, count)| {
+                let mut line = String::from("main");
+                for &address in stack {
+                    line.push(';');
+                    line.push_str(&subroutine_name(address));
+                }
+                format!("{} {}", line, count)
+            })
+            .collect();
+        lines.sort();
+        lines.iter().map(|line| format!("{}\n", line)).collect()
+    }
+
+    /// A text summary: hot addresses, the instruction mix and time per subroutine.
+    pub fn report(&self) -> String {
+        let total = self.instructions.max(1) as f64;
+        let percent = |count: u64| count as f64 * 100.0 / total;
+        let mut report = String::new();
+        // writing to a String can't fail
+        let _ = writeln!(report, "Instructions: {}", self.instructions);
+
+        let _ = writeln!(report, "\nHot addresses");
+        let _ = writeln!(
+            report,
+            "ADDR  OPCODE  {:>10}  {:>6}  INSTRUCTION",
+            "COUNT", "%"
+        );
+        for (address, count) in self.hot_addresses().into_iter().take(REPORT_ADDRESSES) {
+            let opcode = self.addresses[&address].opcode;
+            let text = Op::decode(&opcode.to_be_bytes(), self.variant)
+                .map_or_else(String::new, |op| op.text(Syntax::Classic));
+            let _ = writeln!(
+                report,
+                "{:04X}  {:04X}    {:>10}  {:>6.2}  {}",
+                address,
+                opcode,
+                count,
+                percent(count),
+                text
+            );
+        }
+
+        let _ = writeln!(report, "\nInstruction mix");
+        let mut classes: Vec<(usize, u64)> = self
+            .classes
+            .iter()
+            .copied()
+            .enumerate()
+            .filter(|&(_, count)| count > 0)
+            .collect();
+        classes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        for (class, count) in classes {
+            let _ = writeln!(
+                report,
+                "{:>10}  {:>6.2}  {}",
+                count,
+                percent(count),
+                OPCODE_CLASSES[class]
+            );
+        }
+
+        let _ = writeln!(report, "\nSubroutines");
+        let _ = writeln!(
+            report,
+            "{:<8}  {:>8}  {:>10}  {:>6}  {:>10}  {:>6}",
+            "NAME", "CALLS", "SELF", "%", "TOTAL", "%"
+        );
+        for subroutine in self.subroutines() {
+            let _ = writeln!(
+                report,
+                "{:<8}  {:>8}  {:>10}  {:>6.2}  {:>10}  {:>6.2}",
+                subroutine_name(subroutine.address),
+                subroutine.calls,
+                subroutine.self_count,
+                percent(subroutine.self_count),
+                subroutine.total_count,
+                percent(subroutine.total_count)
+            );
+        }
+        report
+    }
+}
@@ -0,0 +1,101 @@
+/// How FX55 and FX65 leave the I register after storing or loading registers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LoadStoreQuirk {
+    /// I is left pointing where it was
+    #[default]
+    Unchanged,
+    /// I ends up at I + X (CHIP-48)
+    IncrementByX,
+    /// I ends up at I + X + 1, just past the last byte touched (COSMAC VIP, XO-CHIP)
+    IncrementByXPlusOne,
+}
+
+/// Switches for the instructions that behave differently between CHIP-8 interpreters.
+/// The default keeps the behavior this emulator has always had, use one of the presets
+/// to match a specific platform.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Quirks {
+    /// 8XY6/8XYE shift VY and store the result in VX, instead of shifting VX in place
+    pub shift_uses_vy: bool,
+    /// what FX55/FX65 do to I
+    pub load_store: LoadStoreQuirk,
+    /// BNNN jumps to XNN + VX instead of NNN + V0
+    pub jump_uses_vx: bool,
+    /// 8XY1/8XY2/8XY3 reset VF to 0
+    pub logic_resets_vf: bool,
+    /// DXYN clips sprites at the screen edges instead of wrapping them around
+    pub clip_sprites: bool,
+    /// DXYN waits for the next frame before drawing, only in low resolution
+    pub display_wait: bool,
+}
+
+impl Quirks {
+    /// Looks up a preset by the name used on the command line.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "vip" | "cosmac-vip" => Some(Self::cosmac_vip()),
+            "chip48" => Some(Self::chip48()),
+            "schip1.1" | "superchip1.1" => Some(Self::superchip_1_1()),
+            "schip" | "superchip" => Some(Self::modern_schip()),
+            "xo-chip" | "xochip" => Some(Self::xo_chip()),
+            "default" => Some(Self::default()),
+            _ => None,
+        }
+    }
+
+    /// the original interpreter on the COSMAC VIP
+    pub const fn cosmac_vip() -> Self {
+        Self {
+            shift_uses_vy: true,
+            load_store: LoadStoreQuirk::IncrementByXPlusOne,
+            jump_uses_vx: false,
+            logic_resets_vf: true,
+            clip_sprites: true,
+            display_wait: true,
+        }
+    }
+
+    /// CHIP-48 on the HP-48 calculators
+    pub const fn chip48() -> Self {
+        Self {
+            shift_uses_vy: false,
+            load_store: LoadStoreQuirk::IncrementByX,
+            jump_uses_vx: true,
+            logic_resets_vf: false,
+            clip_sprites: true,
+            display_wait: false,
+        }
+    }
+
+    /// SUPER-CHIP 1.1, which still waits for the display interrupt in low resolution
+    pub const fn superchip_1_1() -> Self {
+        Self {
+            shift_uses_vy: false,
+            load_store: LoadStoreQuirk::Unchanged,
+            jump_uses_vx: true,
+            logic_resets_vf: false,
+            clip_sprites: true,
+            display_wait: true,
+        }
+    }
+
+    /// SUPER-CHIP as implemented by most modern interpreters
+    pub const fn modern_schip() -> Self {
+        Self {
+            display_wait: false,
+            ..Self::superchip_1_1()
+        }
+    }
+
+    /// XO-CHIP as implemented by Octo
+    pub const fn xo_chip() -> Self {
+        Self {
+            shift_uses_vy: true,
+            load_store: LoadStoreQuirk::IncrementByXPlusOne,
+            jump_uses_vx: false,
+            logic_resets_vf: false,
+            clip_sprites: false,
+            display_wait: false,
+        }
+    }
+}
@@ -0,0 +1,174 @@
+//! Rewind history: a ring buffer of save states, delta compressed against each other.
+//!
+//! Only the newest snapshot is kept whole. Every older one is stored as the XOR of itself and
+//! the snapshot after it, run length encoded, so a frame where only a few bytes of RAM and a
+//! register or two changed costs a handful of bytes instead of a full 4K+ save state.
+
+use std::collections::VecDeque;
+
+use crate::Emulator;
+
+enum Entry {
+    /// XOR against the next newer snapshot, run length encoded
+    Delta(Vec<u8>),
+    /// stored whole because the snapshots differ in size (e.g. a resolution change)
+    Full(Vec<u8>),
+}
+
+impl Entry {
+    fn size(&self) -> usize {
+        match self {
+            Entry::Delta(bytes) | Entry::Full(bytes) => bytes.len(),
+        }
+    }
+}
+
+pub struct RewindBuffer {
+    capacity: usize,
+    interval: usize,
+    frames_since_snapshot: usize,
+    newest: Option<Vec<u8>>,
+    // oldest at the front, the entry for the snapshot just before `newest` at the back
+    older: VecDeque<Entry>,
+}
+
+impl RewindBuffer {
+    /// Keeps up to `capacity` snapshots, taking one every `interval` frames.
+    pub fn new(capacity: usize, interval: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            interval: interval.max(1),
+            frames_since_snapshot: 0,
+            newest: None,
+            older: VecDeque::new(),
+        }
+    }
+
+    /// Call once per frame, snapshots the emulator every `interval` calls.
+    pub fn record(&mut self, emulator: &Emulator) {
+        self.frames_since_snapshot += 1;
+        if self.frames_since_snapshot < self.interval {
+            return;
+        }
+        self.frames_since_snapshot = 0;
+        self.push(emulator.save_state());
+    }
+
+    fn push(&mut self, state: Vec<u8>) {
+        if let Some(previous) = self.newest.take() {
+            let entry = if previous.len() == state.len() {
+                Entry::Delta(encode_delta(&previous, &state))
+            } else {
+                Entry::Full(previous)
+            };
+            self.older.push_back(entry);
+            // the oldest snapshot only depends on the ones after it, so it can just be dropped
+            if self.older.len() >= self.capacity {
+                self.older.pop_front();
+            }
+        }
+        self.newest = Some(state);
+    }
+
+    /// Restores the most recent snapshot and removes it from the history.
+    /// Returns false, leaving the emulator alone, once the history is used up or if the
+    /// snapshot doesn't load.
+    pub fn rewind(&mut self, emulator: &mut Emulator) -> bool {
+        let Some(newest) = self.newest.take() else {
+            return false;
+        };
+        // the older snapshots are rebuilt from this one, so they're no use without it
+        if emulator.load_state(&newest).is_err() {
+            self.clear();
+            return false;
+        }
+        self.newest = self.older.pop_back().map(|entry| match entry {
+            Entry::Delta(delta) => apply_delta(&newest, &delta),
+            Entry::Full(state) => state,
+        });
+        self.frames_since_snapshot = 0;
+        true
+    }
+
+    /// Number of snapshots in the history.
+    pub fn len(&self) -> usize {
+        self.older.len() + self.newest.is_some() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.newest.is_none()
+    }
+
+    pub fn clear(&mut self) {
+        self.newest = None;
+        self.older.clear();
+        self.frames_since_snapshot = 0;
+    }
+
+    /// Approximate number of bytes the history is using.
+    pub fn memory_usage(&self) -> usize {
+        self.newest.as_ref().map_or(0, Vec::len) + self.older.iter().map(Entry::size).sum::<usize>()
+    }
+}
+
+// the delta is a list of (unchanged run length, changed run length, changed bytes XOR'd),
+// with both lengths as LEB128 varints
+
+fn write_varint(output: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        output.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    output.push(value as u8);
+}
+
+fn read_varint(input: &[u8], position: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = input[*position];
+        *position += 1;
+        value |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+/// Encodes `older` relative to `newer`, both must be the same length.
+fn encode_delta(older: &[u8], newer: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut index = 0;
+    while index < older.len() {
+        let unchanged_start = index;
+        while index < older.len() && older[index] == newer[index] {
+            index += 1;
+        }
+        let changed_start = index;
+        while index < older.len() && older[index] != newer[index] {
+            index += 1;
+        }
+        write_varint(&mut output, changed_start - unchanged_start);
+        write_varint(&mut output, index - changed_start);
+        output.extend((changed_start..index).map(|i| older[i] ^ newer[i]));
+    }
+    output
+}
+
+/// Rebuilds the older snapshot from the newer one and the delta between them.
+fn apply_delta(newer: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut output = newer.to_vec();
+    let mut index = 0;
+    let mut position = 0;
+    while position < delta.len() {
+        index += read_varint(delta, &mut position);
+        let changed = read_varint(delta, &mut position);
+        for byte in &mut output[index..index + changed] {
+            *byte ^= delta[position];
+            position += 1;
+        }
+        index += changed;
+    }
+    output
+}
@@ -0,0 +1,74 @@
+//! Screenshots of the display, as PNG in the window's colors or as plain PBM.
+//!
+//! Images are taken at the display's current resolution (64x32, or 128x64 in SUPER-CHIP high
+//! resolution mode), optionally scaled up by a whole number. PBM is black and white text, one
+//! row of pixels per line, which makes it easy to diff in test fixtures.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::{Emulator, PALETTE};
+
+/// The display as RGB bytes, each pixel blown up to a `scale` by `scale` square.
+/// Returns the width and height of the image along with the pixels.
+pub fn rgb(emulator: &Emulator, scale: usize) -> (usize, usize, Vec<u8>) {
+    let scale = scale.max(1);
+    let width = emulator.display_width();
+    let colors = emulator.pixel_colors();
+    let mut pixels = Vec::with_capacity(colors.len() * scale * scale * 3);
+    for row in colors.chunks(width) {
+        let mut line = Vec::with_capacity(width * scale * 3);
+        for &color in row {
+            for _ in 0..scale {
+                line.extend_from_slice(&PALETTE[color as usize]);
+            }
+        }
+        for _ in 0..scale {
+            pixels.extend_from_slice(&line);
+        }
+    }
+    (width * scale, emulator.display_height() * scale, pixels)
+}
+
+/// Encodes the display as a PNG file.
+pub fn png(emulator: &Emulator, scale: usize) -> Vec<u8> {
+    let (width, height, pixels) = rgb(emulator, scale);
+    encode_png(width, height, &pixels)
+}
+
+/// Encodes RGB bytes as a PNG file.
+pub(crate) fn encode_png(width: usize, height: usize, pixels: &[u8]) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut encoder = png::Encoder::new(&mut data, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    // writing to a Vec can't fail and the size always matches the header
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(pixels))
+        .expect("encoding a PNG in memory");
+    data
+}
+
+/// Renders the display as a plain (P1) PBM, any lit pixel is black.
+pub fn pbm(emulator: &Emulator) -> String {
+    let width = emulator.display_width();
+    let mut pbm = format!("P1\n{} {}\n", width, emulator.display_height());
+    for row in emulator.pixel_colors().chunks(width) {
+        pbm.extend(row.iter().map(|&color| if color != 0 { '1' } else { '0' }));
+        pbm.push('\n');
+    }
+    pbm
+}
+
+/// Saves a screenshot, as PBM if the file name ends in `.pbm` and as PNG otherwise.
+/// `scale` only applies to PNG.
+pub fn save(emulator: &Emulator, path: impl AsRef<Path>, scale: usize) -> io::Result<()> {
+    let path = path.as_ref();
+    if path.extension().is_some_and(|extension| extension == "pbm") {
+        fs::write(path, pbm(emulator))
+    } else {
+        fs::write(path, png(emulator, scale))
+    }
+}
@@ -0,0 +1,587 @@
+//! Save states: a compact binary snapshot of everything in `Emulator`.
+//!
+//! Layout (all integers little endian):
+//! `"C8ST"`, format version (u16), the `Config` the emulator was created with, then the
+//! machine state. A new field means a new version number, and the readers take the version
+//! so older save files still load with the new field defaulted.
+
+use std::fmt;
+
+use crate::display::NUM_PLANES;
+use crate::{
+    Config, Emulator, LoadStoreQuirk, MemoryPolicy, Quirks, StackConfig, StackOverflowBehavior,
+    Variant, AUDIO_PATTERN_SIZE, NUM_FLAGS, NUM_REGISTERS, RAM_SIZE, VIP_STACK_TOP,
+    XO_CHIP_RAM_SIZE,
+};
+
+const MAGIC: &[u8; 4] = b"C8ST";
+/// the version `save_state` writes
+/// 1: initial layout
+/// 2: adds `Config::ticks_per_frame`
+pub const STATE_VERSION: u16 = 2;
+
+/// Reasons a save state can be rejected by `Emulator::load_state`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateError {
+    /// the data doesn't start with the save state header
+    BadMagic,
+    /// the data was written by a newer version of the emulator
+    UnsupportedVersion(u16),
+    /// the data ends part way through the state
+    Truncated,
+    /// a field holds a value the emulator can't be in
+    Invalid(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "unsupported save state version {}", version)
+            }
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::Invalid(field) => write!(f, "save state has an invalid {}", field),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+pub(crate) struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    pub(crate) fn new() -> Self {
+        Self { bytes: Vec::new() }
+    }
+
+    pub(crate) fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    pub(crate) fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub(crate) fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub(crate) fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn bytes(&mut self, value: &[u8]) {
+        self.bytes.extend_from_slice(value);
+    }
+
+    /// Packs pixels 8 to a byte.
+    fn bits(&mut self, pixels: &[bool]) {
+        for chunk in pixels.chunks(8) {
+            let byte = chunk
+                .iter()
+                .enumerate()
+                .fold(0, |byte, (bit, &lit)| byte | ((lit as u8) << bit));
+            self.u8(byte);
+        }
+    }
+}
+
+pub(crate) struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.position == self.data.len()
+    }
+
+    pub(crate) fn bytes(&mut self, length: usize) -> Result<&'a [u8], StateError> {
+        let end = self
+            .position
+            .checked_add(length)
+            .filter(|&end| end <= self.data.len())
+            .ok_or(StateError::Truncated)?;
+        let bytes = &self.data[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub(crate) fn bool(&mut self) -> Result<bool, StateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Invalid("flag")),
+        }
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, StateError> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, StateError> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    fn bits(&mut self, count: usize) -> Result<Vec<bool>, StateError> {
+        let bytes = self.bytes(count.div_ceil(8))?;
+        Ok((0..count)
+            .map(|index| bytes[index / 8] & (1 << (index % 8)) != 0)
+            .collect())
+    }
+}
+
+// stored in place of the stack depth when it's unlimited
+const UNLIMITED_DEPTH: u32 = u32::MAX;
+// deepest fixed size stack a file may ask for, well past any real interpreter's, so a corrupt
+// depth can't make `Emulator::with_config` allocate gigabytes
+const MAX_STACK_DEPTH: usize = 256;
+
+pub(crate) fn write_config(writer: &mut Writer, config: &Config) {
+    writer.u8(match config.variant {
+        Variant::Chip8 => 0,
+        Variant::SuperChip => 1,
+        Variant::XoChip => 2,
+    });
+    writer.u8(match config.memory_policy {
+        MemoryPolicy::Fault => 0,
+        MemoryPolicy::Wrap => 1,
+    });
+    writer.u32(match config.stack.depth {
+        Some(depth) => depth as u32,
+        None => UNLIMITED_DEPTH,
+    });
+    writer.u8(match config.stack.overflow {
+        StackOverflowBehavior::Fault => 0,
+        StackOverflowBehavior::Wrap => 1,
+        StackOverflowBehavior::Ignore => 2,
+    });
+    writer.bool(config.stack.in_ram);
+    let quirks = &config.quirks;
+    writer.bool(quirks.shift_uses_vy);
+    writer.u8(match quirks.load_store {
+        LoadStoreQuirk::Unchanged => 0,
+        LoadStoreQuirk::IncrementByX => 1,
+        LoadStoreQuirk::IncrementByXPlusOne => 2,
+    });
+    writer.bool(quirks.jump_uses_vx);
+    writer.bool(quirks.logic_resets_vf);
+    writer.bool(quirks.clip_sprites);
+    writer.bool(quirks.display_wait);
+    match config.seed {
+        Some(seed) => {
+            writer.bool(true);
+            writer.u64(seed);
+        }
+        None => writer.bool(false),
+    }
+    writer.u32(config.ticks_per_frame as u32);
+}
+
+/// Reads a config written by `write_config` for format `version` of the containing file.
+pub(crate) fn read_config(reader: &mut Reader, version: u16) -> Result<Config, StateError> {
+    let variant = match reader.u8()? {
+        0 => Variant::Chip8,
+        1 => Variant::SuperChip,
+        2 => Variant::XoChip,
+        _ => return Err(StateError::Invalid("variant")),
+    };
+    let memory_policy = match reader.u8()? {
+        0 => MemoryPolicy::Fault,
+        1 => MemoryPolicy::Wrap,
+        _ => return Err(StateError::Invalid("memory policy")),
+    };
+    let depth = match reader.u32()? {
+        UNLIMITED_DEPTH => None,
+        depth => Some(depth as usize),
+    };
+    let overflow = match reader.u8()? {
+        0 => StackOverflowBehavior::Fault,
+        1 => StackOverflowBehavior::Wrap,
+        2 => StackOverflowBehavior::Ignore,
+        _ => return Err(StateError::Invalid("stack overflow behavior")),
+    };
+    let in_ram = reader.bool()?;
+    // a RAM stack can't go deeper than the slots below VIP_STACK_TOP
+    let max_depth = if in_ram {
+        VIP_STACK_TOP / 2
+    } else {
+        MAX_STACK_DEPTH
+    };
+    if depth.is_some_and(|depth| depth > max_depth) {
+        return Err(StateError::Invalid("stack depth"));
+    }
+    let shift_uses_vy = reader.bool()?;
+    let load_store = match reader.u8()? {
+        0 => LoadStoreQuirk::Unchanged,
+        1 => LoadStoreQuirk::IncrementByX,
+        2 => LoadStoreQuirk::IncrementByXPlusOne,
+        _ => return Err(StateError::Invalid("load/store quirk")),
+    };
+    let quirks = Quirks {
+        shift_uses_vy,
+        load_store,
+        jump_uses_vx: reader.bool()?,
+        logic_resets_vf: reader.bool()?,
+        clip_sprites: reader.bool()?,
+        display_wait: reader.bool()?,
+    };
+    let seed = if reader.bool()? {
+        Some(reader.u64()?)
+    } else {
+        None
+    };
+    // version 1 files were all made at the default speed
+    let ticks_per_frame = if version >= 2 {
+        reader.u32()? as usize
+    } else {
+        Config::default().ticks_per_frame
+    };
+    Ok(Config {
+        variant,
+        memory_policy,
+        stack: StackConfig {
+            depth,
+            overflow,
+            in_ram,
+        },
+        quirks,
+        seed,
+        ticks_per_frame,
+    })
+}
+
+/// 64 bit FNV-1a hash, used to identify ROMs and compare machine states.
+pub fn hash(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+impl Emulator {
+    /// Hash of the full save state, two emulators with the same hash are in the same state.
+    pub fn state_hash(&self) -> u64 {
+        hash(&self.save_state())
+    }
+
+    /// Captures the whole machine, including its configuration and random number generator,
+    /// as a versioned binary blob for `load_state`.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        writer.bytes(MAGIC);
+        writer.u16(STATE_VERSION);
+        // store the seed actually in use, so a randomly seeded emulator restores the same way
+        let config = Config {
+            seed: Some(self.seed),
+            ..self.config
+        };
+        write_config(&mut writer, &config);
+        writer.u64(self.seed);
+        writer.u64(self.rng.state());
+
+        writer.u16(self.program_counter);
+        writer.u16(self.i_register);
+        writer.bytes(&self.v_register);
+        writer.u32(self.stack_pointer as u32);
+        writer.u32(self.stack.len() as u32);
+        for &entry in &self.stack {
+            writer.u16(entry);
+        }
+        writer.u16(self.pressed_keys());
+        writer.u8(self.delay_timer);
+        writer.u8(self.sound_timer);
+        writer.bool(self.vblank);
+        writer.bytes(&self.flags);
+        match &self.audio_pattern {
+            Some(pattern) => {
+                writer.bool(true);
+                writer.bytes(pattern);
+            }
+            None => writer.bool(false),
+        }
+        writer.u8(self.pitch);
+
+        writer.bool(self.display.is_high_res());
+        writer.u8(self.display.selected_planes());
+        for plane in 0..NUM_PLANES {
+            writer.bits(self.display.plane(plane));
+        }
+        writer.u32(self.ram.len() as u32);
+        writer.bytes(&self.ram);
+        writer.into_bytes()
+    }
+
+    /// Restores a snapshot made by `save_state`. The data is fully validated before anything
+    /// is changed, so on error the emulator is left as it was.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut reader = Reader::new(data);
+        if reader
+            .bytes(MAGIC.len())
+            .map_err(|_| StateError::BadMagic)?
+            != MAGIC
+        {
+            return Err(StateError::BadMagic);
+        }
+        // older versions are converted to the current layout as they're read
+        let mut restored = match reader.u16()? {
+            version @ 1..=STATE_VERSION => Self::read_state(&mut reader, version)?,
+            version => return Err(StateError::UnsupportedVersion(version)),
+        };
+        if !reader.is_empty() {
+            return Err(StateError::Invalid("length"));
+        }
+
+        // keep the current generator (which may be a custom source) but restore its state
+        let rng_state = restored.rng.state();
+        std::mem::swap(&mut restored.rng, &mut self.rng);
+        restored.rng.set_state(rng_state);
+        // instrumentation covers the whole session, not just the time since the last load
+        restored.tracer = self.tracer.take();
+        restored.profiler = self.profiler.take();
+        restored.coverage = self.coverage.take();
+        restored.heatmap = self.heatmap.take();
+        *self = restored;
+        Ok(())
+    }
+
+    fn read_state(reader: &mut Reader, version: u16) -> Result<Emulator, StateError> {
+        let config = read_config(reader, version)?;
+        let seed = reader.u64()?;
+        let mut emulator = Emulator::with_config(Config {
+            seed: Some(seed),
+            ..config
+        });
+        emulator.rng.set_state(reader.u64()?);
+
+        emulator.program_counter = reader.u16()?;
+        emulator.i_register = reader.u16()?;
+        emulator
+            .v_register
+            .copy_from_slice(reader.bytes(NUM_REGISTERS)?);
+        emulator.stack_pointer = reader.u32()? as usize;
+        let stack_length = reader.u32()? as usize;
+        if config
+            .stack
+            .depth
+            .is_some_and(|depth| depth != stack_length)
+        {
+            return Err(StateError::Invalid("stack"));
+        }
+        emulator.stack = (0..stack_length)
+            .map(|_| reader.u16())
+            .collect::<Result<_, _>>()?;
+        // a RAM stack ignores the stored entries and can grow down until it runs out of memory
+        let slot_limit = if config.stack.in_ram {
+            VIP_STACK_TOP / 2
+        } else {
+            stack_length
+        };
+        let stack_limit = config
+            .stack
+            .depth
+            .map_or(slot_limit, |depth| depth.min(slot_limit));
+        if emulator.stack_pointer > stack_limit {
+            return Err(StateError::Invalid("stack pointer"));
+        }
+        emulator.set_pressed_keys(reader.u16()?);
+        emulator.delay_timer = reader.u8()?;
+        emulator.sound_timer = reader.u8()?;
+        emulator.vblank = reader.bool()?;
+        emulator.flags.copy_from_slice(reader.bytes(NUM_FLAGS)?);
+        if reader.bool()? {
+            let mut pattern = [0; AUDIO_PATTERN_SIZE];
+            pattern.copy_from_slice(reader.bytes(AUDIO_PATTERN_SIZE)?);
+            emulator.audio_pattern = Some(pattern);
+        }
+        emulator.pitch = reader.u8()?;
+
+        let high_res = reader.bool()?;
+        let selected = reader.u8()?;
+        if selected > 0b11 {
+            return Err(StateError::Invalid("plane selection"));
+        }
+        emulator.display.set_high_res(high_res);
+        emulator.display.select_planes(selected);
+        let size = emulator.display.width() * emulator.display.height();
+        for plane in 0..NUM_PLANES {
+            let pixels = reader.bits(size)?;
+            emulator.display.restore_plane(plane, pixels);
+        }
+
+        let ram_size = reader.u32()? as usize;
+        let expected_ram_size = match config.variant {
+            Variant::XoChip => XO_CHIP_RAM_SIZE,
+            _ => RAM_SIZE,
+        };
+        if ram_size != expected_ram_size {
+            return Err(StateError::Invalid("RAM size"));
+        }
+        emulator.ram.copy_from_slice(reader.bytes(ram_size)?);
+        Ok(emulator)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coverage::Coverage;
+    use crate::heatmap::Heatmap;
+
+    // 6005 (V0 := 5) then 1202, a jump to itself
+    const LOOP_ROM: [u8; 4] = [0x60, 0x05, 0x12, 0x02];
+
+    #[test]
+    fn load_state_keeps_coverage() {
+        let mut emulator = Emulator::new();
+        emulator.load(&LOOP_ROM).unwrap();
+        emulator.set_coverage(Some(Coverage::new(&LOOP_ROM, Variant::Chip8)));
+        emulator.tick().unwrap();
+        let state = emulator.save_state();
+        emulator.load_state(&state).unwrap();
+        emulator.tick().unwrap();
+
+        let coverage = emulator
+            .take_coverage()
+            .expect("coverage survives load_state");
+        assert!(coverage.byte(0x200).executed);
+        assert!(coverage.byte(0x202).executed);
+    }
+
+    #[test]
+    fn load_state_keeps_heatmap() {
+        let mut emulator = Emulator::new();
+        emulator.load(&LOOP_ROM).unwrap();
+        emulator.set_heatmap(Some(Heatmap::new()));
+        emulator.tick().unwrap();
+        let state = emulator.save_state();
+        emulator.load_state(&state).unwrap();
+
+        let heatmap = emulator
+            .take_heatmap()
+            .expect("heatmap survives load_state");
+        // the blue channel marks executed bytes
+        assert_ne!(heatmap.color(0x200)[2], 0);
+    }
+
+    // 2204 calls 0x204, which jumps to itself
+    const CALL_ROM: [u8; 6] = [0x22, 0x04, 0x00, 0x00, 0x12, 0x04];
+
+    fn running(config: Config, rom: &[u8]) -> Emulator {
+        let mut emulator = Emulator::with_config(config);
+        emulator.load(rom).unwrap();
+        emulator.tick().unwrap();
+        emulator
+    }
+
+    #[test]
+    fn round_trip_unlimited_ram_stack() {
+        let config = Config {
+            stack: StackConfig {
+                depth: None,
+                in_ram: true,
+                ..StackConfig::default()
+            },
+            ..Config::default()
+        };
+        let emulator = running(config, &CALL_ROM);
+        assert_eq!(emulator.call_stack(), [0x202]);
+        let state = emulator.save_state();
+
+        let mut restored = Emulator::with_config(config);
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.call_stack(), [0x202]);
+        assert_eq!(restored.save_state(), state);
+    }
+
+    #[test]
+    fn round_trip_current_version() {
+        let config = Config {
+            seed: Some(1),
+            ticks_per_frame: 30,
+            ..Config::default()
+        };
+        let emulator = running(config, &CALL_ROM);
+        let state = emulator.save_state();
+        assert_eq!(state[4..6], STATE_VERSION.to_le_bytes());
+
+        let mut restored = Emulator::new();
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.config.ticks_per_frame, 30);
+        assert_eq!(restored.save_state(), state);
+    }
+
+    #[test]
+    fn loads_version_1() {
+        let config = Config {
+            seed: Some(1),
+            ..Config::default()
+        };
+        let emulator = running(config, &CALL_ROM);
+        let mut state = emulator.save_state();
+        // version 1 is the same layout without ticks_per_frame, the last field of the config
+        let mut writer = Writer::new();
+        write_config(&mut writer, &config);
+        let config_end = MAGIC.len() + 2 + writer.into_bytes().len();
+        state.drain(config_end - 4..config_end);
+        state[4..6].copy_from_slice(&1u16.to_le_bytes());
+
+        let mut restored = Emulator::new();
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.save_state(), emulator.save_state());
+    }
+
+    #[test]
+    fn rejects_bad_magic() {
+        let mut state = Emulator::new().save_state();
+        state[0] = b'X';
+        assert_eq!(
+            Emulator::new().load_state(&state),
+            Err(StateError::BadMagic)
+        );
+        assert_eq!(Emulator::new().load_state(b"C8"), Err(StateError::BadMagic));
+    }
+
+    #[test]
+    fn rejects_newer_version() {
+        let mut state = Emulator::new().save_state();
+        state[4..6].copy_from_slice(&(STATE_VERSION + 1).to_le_bytes());
+        assert_eq!(
+            Emulator::new().load_state(&state),
+            Err(StateError::UnsupportedVersion(STATE_VERSION + 1))
+        );
+    }
+
+    #[test]
+    fn rejects_huge_stack_depth() {
+        let mut state = Emulator::new().save_state();
+        // the depth follows the magic, version, variant and memory policy
+        state[8..12].copy_from_slice(&0xFFFF_FFFEu32.to_le_bytes());
+        assert_eq!(
+            Emulator::new().load_state(&state),
+            Err(StateError::Invalid("stack depth"))
+        );
+    }
+}
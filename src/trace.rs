@@ -0,0 +1,287 @@
+//! Instruction tracing: a log of every instruction `Emulator::tick` runs and what it changed.
+//!
+//! Hand the emulator a `Tracer` with `Emulator::set_tracer` and each instruction gets logged with
+//! its address, opcode, disassembly and the registers it changed, plus the fault for one that
+//! makes `tick` fail. Lines are written as it runs or, with `Tracer::keep_last`, only the last
+//! few are kept in memory and written out by `Tracer::finish`.
+
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+
+use crate::debugger::Register;
+use crate::disasm::{Op, Syntax};
+use crate::{EmuError, Registers, Variant};
+
+/// Registers compared before and after each instruction. The program counter changes every time
+/// so it's left out.
+const TRACED_REGISTERS: [Register; 20] = [
+    Register::V(0x0),
+    Register::V(0x1),
+    Register::V(0x2),
+    Register::V(0x3),
+    Register::V(0x4),
+    Register::V(0x5),
+    Register::V(0x6),
+    Register::V(0x7),
+    Register::V(0x8),
+    Register::V(0x9),
+    Register::V(0xa),
+    Register::V(0xb),
+    Register::V(0xc),
+    Register::V(0xd),
+    Register::V(0xe),
+    Register::V(0xf),
+    Register::I,
+    Register::Sp,
+    Register::DelayTimer,
+    Register::SoundTimer,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TraceFormat {
+    /// one aligned line per instruction
+    #[default]
+    Human,
+    /// one JSON object per line
+    Json,
+}
+
+impl TraceFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "human" => Some(TraceFormat::Human),
+            "json" => Some(TraceFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Which instructions get traced. The default traces everything.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceFilter {
+    /// only instructions at these addresses
+    pub addresses: Option<RangeInclusive<u16>>,
+    /// opcode classes to trace, bit N set for opcodes whose first hex digit is N
+    pub opcode_classes: u16,
+}
+
+impl Default for TraceFilter {
+    fn default() -> Self {
+        Self {
+            addresses: None,
+            opcode_classes: 0xffff,
+        }
+    }
+}
+
+impl TraceFilter {
+    pub fn matches(&self, address: u16, opcode: u16) -> bool {
+        self.addresses
+            .as_ref()
+            .is_none_or(|addresses| addresses.contains(&address))
+            && self.opcode_classes & (1 << (opcode >> 12)) != 0
+    }
+}
+
+/// One traced instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub address: u16,
+    pub opcode: u16,
+    /// disassembly in the classic syntax
+    pub text: String,
+    /// registers the instruction changed, with their old and new values
+    pub changes: Vec<(Register, u16, u16)>,
+    /// the fault, if the instruction made `Emulator::tick` fail
+    pub fault: Option<EmuError>,
+}
+
+impl TraceEntry {
+    fn new(
+        address: u16,
+        opcode: u16,
+        text: String,
+        before: &Registers,
+        after: &Registers,
+        fault: Option<EmuError>,
+    ) -> Self {
+        let changes = TRACED_REGISTERS
+            .iter()
+            .map(|&register| (register, register.read(before), register.read(after)))
+            .filter(|(_, old, new)| old != new)
+            .collect();
+        Self {
+            address,
+            opcode,
+            text,
+            changes,
+            fault,
+        }
+    }
+
+    /// The entry as a line of `format`, without the newline.
+    pub fn format(&self, format: TraceFormat) -> String {
+        match format {
+            TraceFormat::Human => {
+                let changes: Vec<String> = self
+                    .changes
+                    .iter()
+                    .map(|&(register, old, new)| match register {
+                        Register::I => format!("{}={:04X}->{:04X}", register, old, new),
+                        _ => format!("{}={:02X}->{:02X}", register, old, new),
+                    })
+                    .collect();
+                let mut line = format!(
+                    "{:04X}  {:04X}  {:<20}  {}",
+                    self.address,
+                    self.opcode,
+                    self.text,
+                    changes.join(" ")
+                );
+                if let Some(fault) = &self.fault {
+                    line = format!("{}  fault: {}", line.trim_end(), fault);
+                }
+                line.trim_end().to_string()
+            }
+            TraceFormat::Json => {
+                let changes: Vec<String> = self
+                    .changes
+                    .iter()
+                    .map(|&(register, old, new)| format!("\"{}\":[{},{}]", register, old, new))
+                    .collect();
+                let fault = self
+                    .fault
+                    .map(|fault| format!(",\"fault\":\"{}\"", json_escape(&fault.to_string())))
+                    .unwrap_or_default();
+                format!(
+                    "{{\"pc\":{},\"opcode\":{},\"text\":\"{}\",\"changes\":{{{}}}{}}}",
+                    self.address,
+                    self.opcode,
+                    json_escape(&self.text),
+                    changes.join(","),
+                    fault
+                )
+            }
+        }
+    }
+}
+
+fn json_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+pub struct Tracer {
+    writer: Box<dyn Write>,
+    format: TraceFormat,
+    filter: TraceFilter,
+    // with keep_last, the entries waiting for finish and how many to keep
+    ring: Option<(VecDeque<TraceEntry>, usize)>,
+    // the first write error, after which tracing stops
+    error: Option<io::Error>,
+}
+
+impl Tracer {
+    /// Traces every instruction to `writer` as it runs.
+    pub fn new(writer: impl Write + 'static, format: TraceFormat) -> Self {
+        Self {
+            writer: Box::new(writer),
+            format,
+            filter: TraceFilter::default(),
+            ring: None,
+            error: None,
+        }
+    }
+
+    pub fn with_filter(mut self, filter: TraceFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Keeps only the last `count` instructions in memory, written out by `finish`.
+    pub fn keep_last(mut self, count: usize) -> Self {
+        self.ring = Some((VecDeque::with_capacity(count), count));
+        self
+    }
+
+    /// The entries kept by `keep_last`, oldest first.
+    pub fn entries(&self) -> impl Iterator<Item = &TraceEntry> {
+        self.ring.iter().flat_map(|(entries, _)| entries.iter())
+    }
+
+    pub(crate) fn record(
+        &mut self,
+        address: u16,
+        bytes: &[u8],
+        variant: Variant,
+        before: &Registers,
+        after: &Registers,
+        fault: Option<EmuError>,
+    ) {
+        let opcode = u16::from_be_bytes([bytes[0], bytes[1]]);
+        if self.error.is_some() || !self.filter.matches(address, opcode) {
+            return;
+        }
+        let text = Op::decode(bytes, variant).map_or_else(
+            || format!("DW 0x{:04X}", opcode),
+            |op| op.text(Syntax::Classic),
+        );
+        let entry = TraceEntry::new(address, opcode, text, before, after, fault);
+        match &mut self.ring {
+            Some((entries, count)) => {
+                if entries.len() == *count {
+                    entries.pop_front();
+                }
+                if *count > 0 {
+                    entries.push_back(entry);
+                }
+            }
+            None => {
+                if let Err(error) = writeln!(self.writer, "{}", entry.format(self.format)) {
+                    self.error = Some(error);
+                }
+            }
+        }
+    }
+
+    /// Writes out anything kept by `keep_last` and flushes. Returns the first write error.
+    pub fn finish(mut self) -> io::Result<()> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        for entry in self.ring.iter().flat_map(|(entries, _)| entries.iter()) {
+            writeln!(self.writer, "{}", entry.format(self.format))?;
+        }
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Emulator;
+
+    #[test]
+    fn traces_the_faulting_instruction() {
+        let mut emulator = Emulator::new();
+        // V0 := 5, then an opcode that doesn't exist
+        emulator.load(&[0x60, 0x05, 0xFF, 0xFF]).unwrap();
+        emulator.set_tracer(Some(
+            Tracer::new(io::sink(), TraceFormat::Human).keep_last(10),
+        ));
+        emulator.tick().unwrap();
+        let fault = emulator.tick().unwrap_err();
+
+        let tracer = emulator.take_tracer().unwrap();
+        let last = tracer.entries().last().unwrap();
+        assert_eq!((last.address, last.opcode), (0x202, 0xFFFF));
+        assert_eq!(last.fault, Some(fault));
+        assert!(last
+            .format(TraceFormat::Human)
+            .ends_with(&format!("fault: {}", fault)));
+        assert!(last
+            .format(TraceFormat::Json)
+            .ends_with(&format!(",\"fault\":\"{}\"}}", fault)));
+        assert_eq!(tracer.entries().count(), 2);
+    }
+}
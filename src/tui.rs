@@ -0,0 +1,660 @@
+//! A debugger that runs in a terminal, for debugging over SSH.
+//!
+//! `Tui::run` takes over the terminal and shows the display drawn with half block characters,
+//! the registers, call stack and keypad, a disassembly around the program counter and the RAM
+//! around `I`, with a command line at the bottom (`help` lists the commands). The emulator runs
+//! through a `Debugger`, a frame's worth of instructions 60 times a second, so breakpoints and
+//! stepping work the same as over GDB.
+//!
+//! Terminals only report key presses, not releases, so CHIP-8 keys are held down for a few
+//! frames each time they're fed, with the `key` command or by typing in keypad mode (Tab).
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use crossterm::cursor::{MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{queue, Command as _};
+
+use crate::debugger::{Command, Condition, Debugger, Register, StopReason};
+use crate::disasm::{Op, Syntax};
+use crate::{Emulator, PALETTE};
+
+const FRAME_TIME: Duration = Duration::from_micros(1_000_000 / 60);
+// how long to wait for input while paused, so a resized terminal still gets redrawn
+const IDLE_POLL: Duration = Duration::from_millis(250);
+// how long a fed key is held when no duration is given
+const DEFAULT_KEY_FRAMES: usize = 5;
+const SIDE_WIDTH: u16 = 30;
+const DISASSEMBLY_WIDTH: u16 = 40;
+// instructions shown above the program counter
+const LINES_BEFORE_PC: usize = 4;
+const BYTES_PER_LINE: usize = 16;
+// return addresses listed, innermost first
+const STACK_LINES: usize = 6;
+const PROMPT: &str = "(chip8) ";
+// the SDL frontend's layout, 1234 QWER ASDF ZXCV
+const KEYPAD_CHARS: [(char, usize); 16] = [
+    ('1', 0x1),
+    ('2', 0x2),
+    ('3', 0x3),
+    ('4', 0xc),
+    ('q', 0x4),
+    ('w', 0x5),
+    ('e', 0x6),
+    ('r', 0xd),
+    ('a', 0x7),
+    ('s', 0x8),
+    ('d', 0x9),
+    ('f', 0xe),
+    ('z', 0xa),
+    ('x', 0x0),
+    ('c', 0xb),
+    ('v', 0xf),
+];
+// the keypad as it's laid out on the COSMAC VIP
+const KEYPAD_ROWS: [[usize; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xc],
+    [0x4, 0x5, 0x6, 0xd],
+    [0x7, 0x8, 0x9, 0xe],
+    [0xa, 0x0, 0xb, 0xf],
+];
+const HELP: &str = "s [n] step, n step over, finish step out, c continue (Esc pauses), \
+                    b <addr> [if <cond>] / d <addr> breakpoints, set <reg> <value>, \
+                    write <addr> <bytes>, ram <addr|i>, key <k> [frames], Tab keypad mode, q quit. \
+                    Numbers are hex";
+
+/// Parses hex, with or without a 0x prefix.
+fn parse_hex(text: &str) -> Option<u32> {
+    let text = text.strip_prefix("0x").unwrap_or(text);
+    u32::from_str_radix(text, 16).ok()
+}
+
+fn palette_color(color: u8) -> Color {
+    let [r, g, b] = PALETTE[color as usize];
+    Color::Rgb { r, g, b }
+}
+
+/// Puts the terminal back however `Tui::run` ends, even on a panic.
+struct TerminalGuard;
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = crossterm::execute!(io::stdout(), ResetColor, Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+pub struct Tui {
+    emulator: Emulator,
+    debugger: Debugger,
+    input: String,
+    last_command: String,
+    // the result of the last command, or why the emulator stopped
+    message: String,
+    keypad_mode: bool,
+    // frames left for each CHIP-8 key that was fed
+    held_keys: [usize; 16],
+    // where the RAM view is, following I when it's None
+    ram_address: Option<usize>,
+    // what's on screen at each position, so only the changes get sent to the terminal
+    screen: HashMap<(u16, u16), String>,
+    quit: bool,
+}
+
+impl Tui {
+    /// Debugs `emulator`, which starts out paused.
+    pub fn new(emulator: Emulator) -> Self {
+        let message = format!(
+            "Paused at {:04X}, c to run, help for commands",
+            emulator.registers().program_counter
+        );
+        Self {
+            emulator,
+            debugger: Debugger::new(),
+            input: String::new(),
+            last_command: String::new(),
+            message,
+            keypad_mode: false,
+            held_keys: [0; 16],
+            ram_address: None,
+            screen: HashMap::new(),
+            quit: false,
+        }
+    }
+
+    /// The debugger, to set breakpoints before `run`.
+    pub fn debugger_mut(&mut self) -> &mut Debugger {
+        &mut self.debugger
+    }
+
+    /// Starts the emulator running, as if `c` was typed.
+    pub fn start(&mut self) {
+        self.debugger.resume(&self.emulator, Command::Continue);
+        self.message = "Running, Esc to pause".to_string();
+    }
+
+    /// Takes over the terminal until the user quits, then hands back the emulator.
+    pub fn run(mut self) -> io::Result<Emulator> {
+        let mut stdout = io::stdout();
+        terminal::enable_raw_mode()?;
+        let _guard = TerminalGuard;
+        queue!(stdout, EnterAlternateScreen, Clear(ClearType::All))?;
+
+        let mut next_frame = Instant::now();
+        while !self.quit {
+            self.draw(&mut stdout)?;
+            let timeout = if self.debugger.is_running() {
+                next_frame.saturating_duration_since(Instant::now())
+            } else {
+                IDLE_POLL
+            };
+            if event::poll(timeout)? {
+                // handle everything waiting, so a paste or fast typing doesn't lag behind
+                loop {
+                    self.handle_event(event::read()?, &mut stdout)?;
+                    if !event::poll(Duration::ZERO)? {
+                        break;
+                    }
+                }
+            }
+            let now = Instant::now();
+            if !self.debugger.is_running() {
+                next_frame = now;
+            } else if now >= next_frame {
+                self.run_frame();
+                next_frame = (next_frame + FRAME_TIME).max(now);
+            }
+        }
+        Ok(self.emulator)
+    }
+
+    fn run_frame(&mut self) {
+        let frame = self.debugger.frame();
+        let ticks = self.emulator.config().ticks_per_frame;
+        if let Some(reason) = self.debugger.run(&mut self.emulator, ticks) {
+            self.message = format!("Stopped: {}", reason);
+        }
+        self.release_keys(self.debugger.frame() - frame);
+    }
+
+    /// Counts down the fed keys by `frames`, letting go of the ones that ran out.
+    fn release_keys(&mut self, frames: u64) {
+        for (key, held) in self.held_keys.iter_mut().enumerate() {
+            if *held > 0 {
+                *held = held.saturating_sub(frames as usize);
+                if *held == 0 {
+                    let _ = self.emulator.keypress(key, false);
+                }
+            }
+        }
+    }
+
+    fn press_key(&mut self, key: usize, frames: usize) {
+        if self.emulator.keypress(key, true).is_ok() {
+            self.held_keys[key] = frames;
+        }
+    }
+
+    fn pause(&mut self) {
+        self.debugger.pause();
+        self.message = format!(
+            "Paused at {:04X}",
+            self.emulator.registers().program_counter
+        );
+    }
+
+    fn handle_event(&mut self, event: Event, out: &mut impl Write) -> io::Result<()> {
+        match event {
+            Event::Resize(..) => {
+                self.screen.clear();
+                queue!(out, Clear(ClearType::All))?;
+            }
+            Event::Key(key) if key.kind != KeyEventKind::Release => self.handle_key(key),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) {
+        let running = self.debugger.is_running();
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Char('c') if control => {
+                if running {
+                    self.pause();
+                } else {
+                    self.input.clear();
+                }
+            }
+            KeyCode::Char('d') if control && self.input.is_empty() => self.quit = true,
+            KeyCode::Esc if running => self.pause(),
+            KeyCode::Esc if self.keypad_mode => self.keypad_mode = false,
+            KeyCode::Esc => self.input.clear(),
+            KeyCode::Tab => self.keypad_mode = !self.keypad_mode,
+            KeyCode::F(5) if running => self.pause(),
+            KeyCode::F(5) => self.execute("c"),
+            KeyCode::F(10) => self.execute("n"),
+            KeyCode::F(11) => self.execute("s"),
+            KeyCode::Char(c) if self.keypad_mode && !control => {
+                let c = c.to_ascii_lowercase();
+                if let Some(&(_, key)) = KEYPAD_CHARS.iter().find(|&&(char, _)| char == c) {
+                    self.press_key(key, DEFAULT_KEY_FRAMES);
+                }
+            }
+            KeyCode::Char(c) if !control => self.input.push(c),
+            KeyCode::Backspace => {
+                self.input.pop();
+            }
+            KeyCode::Enter => {
+                // an empty line repeats the last command, like GDB
+                let line = if self.input.trim().is_empty() {
+                    self.last_command.clone()
+                } else {
+                    std::mem::take(&mut self.input)
+                };
+                self.input.clear();
+                if !line.is_empty() {
+                    self.execute(&line);
+                    self.last_command = line;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn execute(&mut self, line: &str) {
+        match self.command(line) {
+            Ok(Some(message)) => self.message = message,
+            Ok(None) => {}
+            Err(message) => self.message = message,
+        }
+    }
+
+    /// Runs a command line, returning a message to show.
+    fn command(&mut self, line: &str) -> Result<Option<String>, String> {
+        let mut words = line.split_whitespace();
+        let Some(name) = words.next() else {
+            return Ok(None);
+        };
+        let args: Vec<&str> = words.collect();
+        let number = |index: usize, what: &str| {
+            let text = args
+                .get(index)
+                .ok_or_else(|| format!("{} needs {}", name, what))?;
+            parse_hex(text).ok_or_else(|| format!("Invalid number: {}", text))
+        };
+        match name {
+            "s" | "step" => {
+                let count = if args.is_empty() {
+                    1
+                } else {
+                    number(0, "a count")?
+                };
+                for _ in 0..count {
+                    self.debugger.resume(&self.emulator, Command::StepInto);
+                    let frame = self.debugger.frame();
+                    let reason = self.debugger.run(&mut self.emulator, 1);
+                    self.release_keys(self.debugger.frame() - frame);
+                    match reason {
+                        Some(StopReason::Step) | None => {}
+                        Some(reason) => return Ok(Some(format!("Stopped: {}", reason))),
+                    }
+                }
+                Ok(Some(format!(
+                    "Stepped to {:04X}",
+                    self.emulator.registers().program_counter
+                )))
+            }
+            "n" | "next" => {
+                self.debugger.resume(&self.emulator, Command::StepOver);
+                Ok(None)
+            }
+            "finish" | "out" => {
+                self.debugger.resume(&self.emulator, Command::StepOut);
+                Ok(None)
+            }
+            "c" | "continue" => {
+                self.start();
+                Ok(None)
+            }
+            "b" | "break" => {
+                let address = number(0, "an address")? as u16;
+                match args.get(1) {
+                    None => self.debugger.add_breakpoint(address),
+                    Some(&"if") => {
+                        // the condition is everything after the if, spaces and all
+                        let source = line
+                            .split_once(" if ")
+                            .map(|(_, condition)| condition)
+                            .unwrap_or("");
+                        let condition = Condition::parse(source)
+                            .map_err(|error| format!("Invalid condition: {}", error))?;
+                        self.debugger.add_conditional_breakpoint(address, condition);
+                    }
+                    Some(_) => return Err("Usage: b <addr> [if <condition>]".to_string()),
+                }
+                Ok(Some(format!("Breakpoint at {:04X}", address)))
+            }
+            "d" | "delete" => {
+                let address = number(0, "an address")? as u16;
+                if self.debugger.remove_breakpoint(address) {
+                    Ok(Some(format!("Deleted the breakpoint at {:04X}", address)))
+                } else {
+                    Err(format!("No breakpoint at {:04X}", address))
+                }
+            }
+            "set" => {
+                let name = args.first().ok_or("set needs a register")?;
+                let register = Register::from_name(name)
+                    .ok_or_else(|| format!("Unknown register: {}", name))?;
+                let value = number(1, "a value")?;
+                let mut registers = self.emulator.registers();
+                let byte = || u8::try_from(value).map_err(|_| format!("{} is a byte", register));
+                match register {
+                    Register::V(index) => registers.v[index as usize] = byte()?,
+                    Register::I => registers.i = value as u16,
+                    Register::Pc => registers.program_counter = value as u16,
+                    Register::DelayTimer => registers.delay_timer = byte()?,
+                    Register::SoundTimer => registers.sound_timer = byte()?,
+                    Register::Sp => return Err("SP can't be set".to_string()),
+                }
+                self.emulator
+                    .set_registers(&registers)
+                    .map_err(|error| format!("Can't set {}: {}", register, error))?;
+                Ok(Some(format!("{} = {:X}", register, value)))
+            }
+            "write" => {
+                let address = number(0, "an address")? as usize;
+                let bytes = (1..args.len().max(2))
+                    .map(|index| {
+                        number(index, "bytes")?
+                            .try_into()
+                            .map_err(|_| format!("Not a byte: {}", args[index]))
+                    })
+                    .collect::<Result<Vec<u8>, String>>()?;
+                self.emulator
+                    .write_memory(address, &bytes)
+                    .map_err(|error| format!("Can't write there: {}", error))?;
+                Ok(Some(format!(
+                    "Wrote {} bytes at {:04X}",
+                    bytes.len(),
+                    address
+                )))
+            }
+            "ram" => match args.first() {
+                Some(text) if text.eq_ignore_ascii_case("i") => {
+                    self.ram_address = None;
+                    Ok(None)
+                }
+                _ => {
+                    self.ram_address = Some(number(0, "an address or i")? as usize);
+                    Ok(None)
+                }
+            },
+            "key" => {
+                let key = number(0, "a key")? as usize;
+                if key > 0xf {
+                    return Err(format!("No key {:X}", key));
+                }
+                let frames = match args.get(1) {
+                    Some(frames) => frames
+                        .parse()
+                        .map_err(|_| format!("Invalid number of frames: {}", frames))?,
+                    None => DEFAULT_KEY_FRAMES,
+                };
+                self.press_key(key, frames);
+                Ok(Some(format!("Holding {:X} for {} frames", key, frames)))
+            }
+            "keypad" => {
+                self.keypad_mode = !self.keypad_mode;
+                Ok(None)
+            }
+            "h" | "help" => Ok(Some(HELP.to_string())),
+            "q" | "quit" => {
+                self.quit = true;
+                Ok(None)
+            }
+            _ => Err(format!("Unknown command: {} (try help)", name)),
+        }
+    }
+
+    /// Writes `text` at `x`, `y` unless it's already there.
+    fn put(&mut self, out: &mut impl Write, x: u16, y: u16, text: String) -> io::Result<()> {
+        if self.screen.get(&(x, y)) != Some(&text) {
+            queue!(out, MoveTo(x, y), Print(&text), ResetColor)?;
+            self.screen.insert((x, y), text);
+        }
+        Ok(())
+    }
+
+    /// Writes plain lines padded to `width`, cut off at the edge of the terminal.
+    fn put_lines(
+        &mut self,
+        out: &mut impl Write,
+        (x, y): (u16, u16),
+        (width, height): (u16, u16),
+        lines: &[String],
+    ) -> io::Result<()> {
+        let (columns, rows) = terminal::size()?;
+        let width = width.min(columns.saturating_sub(x)) as usize;
+        for row in 0..height {
+            if y + row >= rows {
+                break;
+            }
+            let line = lines.get(row as usize).map_or("", String::as_str);
+            let text: String = line
+                .chars()
+                .chain(std::iter::repeat(' '))
+                .take(width)
+                .collect();
+            self.put(out, x, y + row, text)?;
+        }
+        Ok(())
+    }
+
+    fn draw(&mut self, out: &mut impl Write) -> io::Result<()> {
+        let (columns, rows) = terminal::size()?;
+        let width = self.emulator.display_width();
+        let display_rows = self.emulator.display_height().div_ceil(2) as u16;
+        self.draw_display(out, columns)?;
+
+        // the side panel goes next to the display if there's room, under it if not
+        let side = self.side_panel();
+        let side_height = side.len() as u16;
+        let (side_x, side_y, top_height) = if columns >= width as u16 + 2 + SIDE_WIDTH {
+            (width as u16 + 2, 0, display_rows.max(side_height))
+        } else {
+            (0, display_rows + 1, display_rows + 1 + side_height)
+        };
+        self.put_lines(out, (side_x, side_y), (SIDE_WIDTH, side_height), &side)?;
+
+        let panel_y = top_height + 1;
+        let panel_height = rows.saturating_sub(panel_y + 2);
+        let disassembly = self.disassembly(panel_height.saturating_sub(1) as usize);
+        self.put_lines(
+            out,
+            (0, panel_y),
+            (DISASSEMBLY_WIDTH, panel_height),
+            &disassembly,
+        )?;
+        let ram_x = DISASSEMBLY_WIDTH + 2;
+        let ram = self.ram_view(panel_height.saturating_sub(1) as usize);
+        self.put_lines(
+            out,
+            (ram_x, panel_y),
+            (columns.saturating_sub(ram_x), panel_height),
+            &ram,
+        )?;
+
+        let state = match (self.debugger.is_running(), self.keypad_mode) {
+            (true, true) => "[running, keypad] ",
+            (true, false) => "[running] ",
+            (false, true) => "[keypad] ",
+            (false, false) => "",
+        };
+        let status = format!("{}{}", state, self.message);
+        let prompt = format!("{}{}", PROMPT, self.input);
+        let status_y = rows.saturating_sub(2);
+        self.put_lines(out, (0, status_y), (columns, 2), &[status, prompt])?;
+        let cursor_x = (PROMPT.len() + self.input.chars().count()) as u16;
+        queue!(out, MoveTo(cursor_x.min(columns), rows.saturating_sub(1)))?;
+        out.flush()
+    }
+
+    /// Two rows of pixels per line of text: the top one in the foreground color of a `▀`, the
+    /// bottom one in the background.
+    fn draw_display(&mut self, out: &mut impl Write, columns: u16) -> io::Result<()> {
+        let width = self.emulator.display_width();
+        let height = self.emulator.display_height();
+        let colors = self.emulator.pixel_colors();
+        for y in (0..height).step_by(2) {
+            let mut line = String::new();
+            let mut current = None;
+            for x in 0..width.min(columns as usize) {
+                let top = colors[y * width + x];
+                let bottom = colors.get((y + 1) * width + x).copied().unwrap_or(0);
+                if current != Some((top, bottom)) {
+                    // writing ANSI codes to a String can't fail
+                    let _ = SetForegroundColor(palette_color(top)).write_ansi(&mut line);
+                    let _ = SetBackgroundColor(palette_color(bottom)).write_ansi(&mut line);
+                    current = Some((top, bottom));
+                }
+                line.push('▀');
+            }
+            self.put(out, 0, (y / 2) as u16, line)?;
+        }
+        Ok(())
+    }
+
+    /// Registers, the call stack and the keypad.
+    fn side_panel(&self) -> Vec<String> {
+        let registers = self.emulator.registers();
+        let mut lines = vec![
+            format!(
+                "PC {:04X}  I {:04X}  SP {}",
+                registers.program_counter, registers.i, registers.stack_pointer
+            ),
+            format!(
+                "DT {:02X}    ST {:02X}",
+                registers.delay_timer, registers.sound_timer
+            ),
+        ];
+        for (row, values) in registers.v.chunks(4).enumerate() {
+            let mut line = String::new();
+            for (column, value) in values.iter().enumerate() {
+                let _ = write!(line, "V{:X} {:02X}  ", row * 4 + column, value);
+            }
+            lines.push(line);
+        }
+
+        lines.push(String::new());
+        let stack = self.emulator.call_stack();
+        lines.push(format!("Stack ({})", stack.len()));
+        for (depth, address) in stack.iter().enumerate().rev().take(STACK_LINES) {
+            lines.push(format!("  {:>2}  {:04X}", depth, address));
+        }
+        if stack.len() > STACK_LINES {
+            lines.push("  ...".to_string());
+        }
+
+        lines.push(String::new());
+        lines.push("Keys".to_string());
+        let pressed = self.emulator.pressed_keys();
+        for row in KEYPAD_ROWS {
+            let line: String = row
+                .iter()
+                .map(|&key| {
+                    if pressed & (1 << key) != 0 {
+                        format!("[{:X}]", key)
+                    } else {
+                        format!(" {:X} ", key)
+                    }
+                })
+                .collect();
+            lines.push(format!("  {}", line));
+        }
+        lines
+    }
+
+    /// A title and `count` instructions around the program counter.
+    fn disassembly(&self, count: usize) -> Vec<String> {
+        let ram = self.emulator.ram();
+        let variant = self.emulator.config().variant;
+        let pc = self.emulator.registers().program_counter as usize;
+        let breakpoints: Vec<u16> = self
+            .debugger
+            .breakpoints()
+            .map(|(address, _)| address)
+            .collect();
+        let mut lines = vec!["Disassembly".to_string()];
+        // going backwards is guesswork, so start a few instructions' worth of bytes back
+        let mut address = pc.saturating_sub(LINES_BEFORE_PC * 2);
+        while lines.len() <= count && address + 1 < ram.len() {
+            let (size, text) = match Op::decode(&ram[address..], variant) {
+                // an instruction that overlaps the program counter was a wrong guess
+                Some(op) if address < pc && address + op.size() as usize > pc => {
+                    (pc - address, None)
+                }
+                Some(op) => (op.size() as usize, Some(op.text(Syntax::Classic))),
+                None => (2, None),
+            };
+            let bytes = &ram[address..(address + size).min(ram.len())];
+            let opcode: String = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+            let text = text.unwrap_or_else(|| format!("DW 0x{:02X}{:02X}", bytes[0], bytes[1]));
+            let marker = match (breakpoints.contains(&(address as u16)), address == pc) {
+                (true, true) => "*>",
+                (true, false) => "* ",
+                (false, true) => " >",
+                (false, false) => "  ",
+            };
+            lines.push(format!(
+                "{} {:04X}  {:<8}  {}",
+                marker, address, opcode, text
+            ));
+            address += size;
+        }
+        lines
+    }
+
+    /// A title and `count` lines of hex around `I`, or the address picked with `ram`.
+    fn ram_view(&self, count: usize) -> Vec<String> {
+        let ram = self.emulator.ram();
+        let i = self.emulator.registers().i as usize;
+        let (title, focus) = match self.ram_address {
+            Some(address) => (format!("RAM at {:04X}", address), address),
+            None => (format!("RAM at I ({:04X})", i), i),
+        };
+        let mut lines = vec![title];
+        let start = (focus - focus % BYTES_PER_LINE).saturating_sub(BYTES_PER_LINE);
+        for line in 0..count {
+            let address = start + line * BYTES_PER_LINE;
+            if address >= ram.len() {
+                break;
+            }
+            let end = (address + BYTES_PER_LINE).min(ram.len());
+            // the byte at I is bracketed
+            let mut text = format!("{:04X} ", address);
+            for (offset, byte) in ram[address..end].iter().enumerate() {
+                let byte_address = address + offset;
+                let separator = if byte_address == i {
+                    '['
+                } else if byte_address == i + 1 && offset > 0 {
+                    ']'
+                } else {
+                    ' '
+                };
+                let _ = write!(text, "{}{:02X}", separator, byte);
+            }
+            if end == i + 1 {
+                text.push(']');
+            }
+            lines.push(text);
+        }
+        lines
+    }
+}
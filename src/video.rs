@@ -0,0 +1,165 @@
+//! Recording the display over time, as an animated GIF or a raw RGB frame stream.
+//!
+//! Every frame is drawn at high resolution size like the SDL window, with low resolution pixels
+//! doubled, so a program switching resolution doesn't change the size of the video.
+//!
+//! The raw stream is `rgb24` at 60 frames a second with no header, for piping into a video
+//! encoder, e.g. `ffmpeg -f rawvideo -pix_fmt rgb24 -s 128x64 -r 60 -i - out.mp4`.
+
+use std::io::{self, Write};
+
+use crate::{Emulator, HIRES_SCREEN_HEIGHT, HIRES_SCREEN_WIDTH, PALETTE};
+
+const FRAMES_PER_SECOND: usize = 60;
+// GIF delays are in hundredths of a second and most viewers slow anything under 2 right down
+const MIN_GIF_DELAY: u32 = 2;
+
+/// Size of the recorded frames at `scale`.
+pub fn frame_size(scale: usize) -> (usize, usize) {
+    let scale = scale.max(1);
+    (HIRES_SCREEN_WIDTH * scale, HIRES_SCREEN_HEIGHT * scale)
+}
+
+/// The display as `PALETTE` indices, scaled up to `frame_size`.
+fn frame_colors(emulator: &Emulator, scale: usize) -> Vec<u8> {
+    let (width, height) = frame_size(scale);
+    let pixel_size = width / emulator.display_width();
+    let colors = emulator.pixel_colors();
+    let mut frame = Vec::with_capacity(width * height);
+    for row in colors.chunks(emulator.display_width()) {
+        let mut line = Vec::with_capacity(width);
+        for &color in row {
+            line.extend(std::iter::repeat_n(color, pixel_size));
+        }
+        for _ in 0..pixel_size {
+            frame.extend_from_slice(&line);
+        }
+    }
+    frame
+}
+
+fn gif_error(error: gif::EncodingError) -> io::Error {
+    match error {
+        gif::EncodingError::Io(error) => error,
+        error => io::Error::other(error),
+    }
+}
+
+/// Records an animated GIF. Call `capture` once per frame and `finish` at the end.
+///
+/// Frames that look the same as the one before only lengthen its delay, so a static screen
+/// costs nothing. Changes faster than GIFs can show are dropped.
+pub struct GifRecorder<W: Write> {
+    encoder: gif::Encoder<W>,
+    scale: usize,
+    /// the frame waiting for its delay to be known
+    pending: Option<Vec<u8>>,
+    /// number of frames captured so far
+    frames: usize,
+    /// total delay of the frames written to the file, in hundredths of a second
+    written: u32,
+}
+
+impl<W: Write> GifRecorder<W> {
+    pub fn new(writer: W, scale: usize) -> io::Result<Self> {
+        let (width, height) = frame_size(scale);
+        let palette: Vec<u8> = PALETTE.iter().flatten().copied().collect();
+        let mut encoder =
+            gif::Encoder::new(writer, width as u16, height as u16, &palette).map_err(gif_error)?;
+        encoder
+            .set_repeat(gif::Repeat::Infinite)
+            .map_err(gif_error)?;
+        Ok(Self {
+            encoder,
+            scale,
+            pending: None,
+            frames: 0,
+            written: 0,
+        })
+    }
+
+    pub fn capture(&mut self, emulator: &Emulator) -> io::Result<()> {
+        let colors = frame_colors(emulator, self.scale);
+        if self.pending.as_ref() != Some(&colors) {
+            // the pending frame has been on screen until now
+            let delay = self.elapsed() - self.written;
+            match self.pending.take() {
+                Some(pending) if delay >= MIN_GIF_DELAY => self.write(pending, delay)?,
+                // too short to show, the new frame takes over its time
+                _ => {}
+            }
+            self.pending = Some(colors);
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Number of frames captured, including ones that were merged or dropped.
+    pub fn frame_count(&self) -> usize {
+        self.frames
+    }
+
+    /// Writes the last frame and the end of the file, returning the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(pending) = self.pending.take() {
+            let delay = (self.elapsed() - self.written).max(MIN_GIF_DELAY);
+            self.write(pending, delay)?;
+        }
+        self.encoder.into_inner()
+    }
+
+    /// Time since recording started, in hundredths of a second.
+    fn elapsed(&self) -> u32 {
+        ((self.frames * 100 + FRAMES_PER_SECOND / 2) / FRAMES_PER_SECOND) as u32
+    }
+
+    fn write(&mut self, colors: Vec<u8>, delay: u32) -> io::Result<()> {
+        let (width, height) = frame_size(self.scale);
+        let frame = gif::Frame {
+            width: width as u16,
+            height: height as u16,
+            delay: delay.min(u16::MAX as u32) as u16,
+            buffer: colors.into(),
+            ..gif::Frame::default()
+        };
+        self.encoder.write_frame(&frame).map_err(gif_error)?;
+        self.written += delay;
+        Ok(())
+    }
+}
+
+/// Writes every frame as raw RGB bytes, see the module docs.
+pub struct RawRecorder<W: Write> {
+    writer: W,
+    scale: usize,
+    frames: usize,
+}
+
+impl<W: Write> RawRecorder<W> {
+    pub fn new(writer: W, scale: usize) -> Self {
+        Self {
+            writer,
+            scale,
+            frames: 0,
+        }
+    }
+
+    pub fn capture(&mut self, emulator: &Emulator) -> io::Result<()> {
+        let rgb: Vec<u8> = frame_colors(emulator, self.scale)
+            .into_iter()
+            .flat_map(|color| PALETTE[color as usize])
+            .collect();
+        self.writer.write_all(&rgb)?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frames
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}